@@ -5,4 +5,5 @@ fn main() {
     println!("cargo:rerun-if-changed=riscv64gcv-unknown-none-elf.ld");
     println!("cargo:rerun-if-changed=x86_64-unknown-none.json");
     println!("cargo:rerun-if-changed=x86_64-unknown-none.ld");
+    println!("cargo:rerun-if-env-changed=XRTF_GNTTAB_MAX_MAPPINGS");
 }
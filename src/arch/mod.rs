@@ -36,3 +36,22 @@ pub fn map_4k_frame<T>(pfn: u64, #[allow(unused)] encrypted: bool) -> Option<Non
         NonNull::new((pfn << 12) as *mut T)
     }
 }
+
+/// Guest frame backing `addr`, if mapped.
+pub fn virt_to_pfn(addr: usize) -> Option<u64> {
+    #[cfg(target_arch = "x86_64")]
+    {
+        use crate::arch::x86_64::mm::translate;
+        use ::x86_64::VirtAddr;
+
+        let paddr = translate(VirtAddr::try_new(addr as u64).ok()?)?;
+
+        Some(paddr.as_u64() >> 12)
+    }
+
+    #[cfg(not(target_arch = "x86_64"))]
+    {
+        /* Assume flat memory model */
+        Some(addr as u64 >> 12)
+    }
+}
@@ -38,6 +38,17 @@ unsafe extern "C" fn raw_ghcb_vc_handler() {
     naked_asm!(include_str!("isr.s"), options(att_syntax), EXCEPTION_HANDLER = sym ghcb_vc_handler)
 }
 
+/// Install the entry stub `handler` for interrupt `vector`.
+///
+/// # Safety
+///
+/// `handler` must be an interrupt entry point (ending with iretq).
+pub unsafe fn set_handler(vector: u8, handler: unsafe extern "C" fn()) {
+    let idt = unsafe { &mut *IDT.get() };
+
+    unsafe { idt[vector].set_handler_addr(VirtAddr::new(handler as usize as u64)) };
+}
+
 pub fn setup() {
     let idt = unsafe { &mut *IDT.get() };
 
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Interrupt flag control
+//!
+//! Interrupts are disabled on entry, and only event channel upcalls are
+//! expected once enabled (see [`crate::xen::event::upcall`]).
+
+use x86_64::instructions::interrupts;
+
+pub use interrupts::{are_enabled, disable, enable, without_interrupts};
+
+/// Keeps interrupts disabled while alive, restoring the previous state on
+/// drop.
+#[must_use]
+pub struct InterruptGuard {
+    was_enabled: bool,
+}
+
+impl InterruptGuard {
+    pub fn new() -> Self {
+        let was_enabled = are_enabled();
+        disable();
+
+        Self { was_enabled }
+    }
+}
+
+impl Default for InterruptGuard {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for InterruptGuard {
+    fn drop(&mut self) {
+        if self.was_enabled {
+            enable();
+        }
+    }
+}
+
+/// Enable interrupts and halt until the next one, without letting an
+/// interrupt slip in between.
+pub fn enable_and_wait() {
+    interrupts::enable_and_hlt();
+}
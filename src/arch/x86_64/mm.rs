@@ -16,7 +16,9 @@ use x86_64::{
     PhysAddr, VirtAddr,
     instructions::tlb,
     registers::control::Cr3,
-    structures::paging::{PageSize, PageTable, PageTableFlags, PhysFrame, Size2MiB, Size4KiB},
+    structures::paging::{
+        PageSize, PageTable, PageTableFlags, PhysFrame, Size1GiB, Size2MiB, Size4KiB,
+    },
 };
 
 #[unsafe(no_mangle)]
@@ -28,6 +30,9 @@ static L2_TABLE: SyncUnsafeCell<PageTable> = SyncUnsafeCell::new(PageTable::new(
 #[unsafe(no_mangle)]
 static L1_TABLE: SyncUnsafeCell<PageTable> = SyncUnsafeCell::new(PageTable::new());
 
+/// Number of frames that can be mapped in the L1 window.
+pub const L1_WINDOW_FRAMES: usize = 512;
+
 #[unsafe(no_mangle)]
 pub static mut MEMORY_ENCRYPT_FLAG: PageTableFlags = PageTableFlags::empty();
 
@@ -88,3 +93,25 @@ pub unsafe fn map_frame(frame: PhysFrame<Size4KiB>, encrypted: bool) -> Option<V
     tlb::flush(addr);
     Some(addr)
 }
+
+/// Physical address backing `addr`, if mapped.
+pub fn translate(addr: VirtAddr) -> Option<PhysAddr> {
+    let addr = addr.as_u64();
+
+    if !(Size2MiB::SIZE..2 * Size2MiB::SIZE).contains(&addr) {
+        return (addr < Size1GiB::SIZE).then(|| PhysAddr::new(addr));
+    }
+
+    // SAFETY: Only read, L1 entries are updated by map_frame on this CPU.
+    let l1 = unsafe { &*L1_TABLE.get() };
+    let entry = &l1[((addr - Size2MiB::SIZE) / Size4KiB::SIZE) as usize];
+
+    if entry.is_unused() {
+        return None;
+    }
+
+    // The encryption bit may be within the address bits.
+    let frame = entry.addr().as_u64() & !unsafe { MEMORY_ENCRYPT_FLAG }.bits();
+
+    Some(PhysAddr::new(frame + addr % Size4KiB::SIZE))
+}
@@ -3,9 +3,12 @@
 
 use core::arch::x86_64::__cpuid;
 
+// The boot code needs the linker script, which host tests don't use.
+#[cfg(not(test))]
 pub mod asm;
 pub mod gdt;
 pub mod idt;
+pub mod interrupts;
 pub mod layout;
 pub mod mm;
 pub mod sev;
@@ -24,7 +27,9 @@ pub fn setup_cpu_vendor() {
         &leaf.edx.to_ne_bytes(),
         &leaf.ecx.to_ne_bytes(),
     ) {
-        (b"Auth", b"enti", b"cAMD") => unsafe { CPU_VENDOR = CpuVendor::Amd },
+        (b"Auth", b"enti", b"cAMD") | (b"Hygo", b"nGen", b"uine") => unsafe {
+            CPU_VENDOR = CpuVendor::Amd
+        },
         _ => (),
     }
 }
@@ -68,4 +68,30 @@ fn xrtf_main(info: &dyn bootinfo::Info) {
 
         println!("IA32_APIC_BASE: {:08x}", Msr::new(0x1b).read());
     }
+
+    if xrtf::xen::detect().is_some() {
+        println!("-- Xen version --");
+
+        match xrtf::xen::version::XenVersion::query() {
+            Ok(version) => {
+                println!("{version}");
+                println!("changeset: {}", version.changeset);
+                println!(
+                    "compiled by {}@{} with {} on {}",
+                    version.compile_info.compile_by,
+                    version.compile_info.compile_domain,
+                    version.compile_info.compiler,
+                    version.compile_info.compile_date
+                );
+                println!("capabilities: {}", version.capabilities);
+                println!("virt_start: {:016x}", version.virt_start);
+                println!("features: {:?}", version.features);
+
+                if let Some(build_id) = version.build_id {
+                    println!("build id: {build_id}");
+                }
+            }
+            Err(e) => println!("Unable to query Xen version: {e}"),
+        }
+    }
 }
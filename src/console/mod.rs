@@ -60,11 +60,11 @@ macro_rules! println {
 
 pub fn init() {
     // Try to initialize Xen PV console
-    unsafe {
-        if let Some(xen) = XenConsole::new() {
-            *DEFAULT.borrow_mut() = Console::Xen(xen);
-            return;
-        }
+    if crate::xen::detect::is_present()
+        && let Some(xen) = unsafe { XenConsole::new() }
+    {
+        *DEFAULT.borrow_mut() = Console::Xen(xen);
+        return;
     }
 
     // Fallback to UART
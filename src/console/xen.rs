@@ -9,11 +9,15 @@ use crate::{
     arch::map_4k_frame,
     xen::{
         event::EventChannel,
-        hvm_get_param,
+        hvm::{HvmParam, get_param},
         ring::{XenRing, XenRingError},
+        sched::{deadline_after, wait_for_event, yield_now},
     },
 };
 
+/// Longest wait for the backend before checking the ring again.
+const CONSOLE_WAIT_NS: u64 = 10_000_000;
+
 #[repr(C)]
 #[derive(VolatileFieldAccess)]
 pub struct XenConsInterface {
@@ -25,9 +29,6 @@ pub struct XenConsInterface {
     out_prod: u32,
 }
 
-const HVM_PARAM_CONSOLE_PFN: u32 = 17;
-const HVM_PARAM_CONSOLE_EVTCHN: u32 = 18;
-
 pub struct XenConsole {
     interface: XenRing<'static>,
     event_channel: EventChannel,
@@ -38,8 +39,8 @@ unsafe impl Sync for XenConsole {}
 
 impl XenConsole {
     pub unsafe fn new() -> Option<Self> {
-        let pfn = unsafe { hvm_get_param(HVM_PARAM_CONSOLE_PFN) };
-        let evtchn = unsafe { hvm_get_param(HVM_PARAM_CONSOLE_EVTCHN) };
+        let pfn = get_param(HvmParam::ConsolePfn).ok()?;
+        let evtchn = get_param(HvmParam::ConsoleEvtchn).ok()?;
 
         if pfn == 0 {
             return None;
@@ -68,13 +69,25 @@ impl fmt::Write for XenConsole {
             }
 
             while let Err(e) = self.interface.write(&[byte]) {
-                assert_ne!(e, XenRingError::NotReady);
-
-                self.event_channel.send();
+                if e != XenRingError::NotReady {
+                    return Err(fmt::Error);
+                }
+
+                // Kick the backend and sleep until it has consumed some data,
+                // don't wait forever in case we miss its notification.
+                self.event_channel.send().map_err(|_| fmt::Error)?;
+
+                // Without the system time (not on x86, or the shared info
+                // page isn't mapped yet), only yield before retrying. The
+                // console must not map the shared info page itself.
+                match deadline_after(CONSOLE_WAIT_NS) {
+                    Some(deadline) => wait_for_event(self.event_channel, Some(deadline)).map(drop),
+                    None => yield_now(),
+                }
+                .ok();
             }
         }
 
-        self.event_channel.send();
-        Ok(())
+        self.event_channel.send().map_err(|_| fmt::Error)
     }
 }
@@ -23,6 +23,16 @@ unsafe fn rdtsc() -> u64 {
     r
 }
 
+/// Current value of the cycle counter (TSC, CNTVCT or time CSR).
+#[inline]
+pub fn read_tsc() -> u64 {
+    // _rdtsc is safe on current toolchains.
+    #[allow(unused_unsafe)]
+    unsafe {
+        rdtsc()
+    }
+}
+
 #[cfg(target_arch = "aarch64")]
 #[inline]
 fn pause() {
@@ -1,13 +1,11 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright © 2019 Intel Corporation
 
-#![no_std]
-#![no_main]
+#![cfg_attr(not(test), no_std)]
+#![cfg_attr(not(test), no_main)]
 #![feature(sync_unsafe_cell)]
 #![cfg_attr(target_arch = "riscv64", feature(riscv_ext_intrinsics))]
 
-use core::panic::PanicInfo;
-
 #[macro_use]
 pub mod console;
 
@@ -27,13 +25,14 @@ pub mod logger;
 pub mod pvh;
 pub mod xen;
 
+#[cfg(not(test))]
 #[panic_handler]
-fn panic(info: &PanicInfo) -> ! {
+fn panic(info: &core::panic::PanicInfo) -> ! {
     println!("PANIC: {info}");
     delay::stop_cpu()
 }
 
-#[cfg(target_arch = "x86_64")]
+#[cfg(all(target_arch = "x86_64", not(test)))]
 #[unsafe(no_mangle)]
 pub extern "C" fn rust64_start(pvh_info: &pvh::StartInfo) -> ! {
     arch::x86_64::sse::enable_sse();
@@ -41,6 +40,7 @@ pub extern "C" fn rust64_start(pvh_info: &pvh::StartInfo) -> ! {
     arch::x86_64::sev::setup();
     arch::x86_64::idt::setup();
     arch::x86_64::setup_cpu_vendor();
+    xen::hypercall::init();
 
     console::init();
     logger::init();
@@ -49,16 +49,20 @@ pub extern "C" fn rust64_start(pvh_info: &pvh::StartInfo) -> ! {
 
     unsafe { xrtf_main(info) };
 
+    #[cfg(feature = "hypercall-stats")]
+    xen::hypercall::stats::dump();
+
     delay::stop_cpu()
 }
 
-#[cfg(target_arch = "aarch64")]
+#[cfg(all(target_arch = "aarch64", not(test)))]
 #[unsafe(no_mangle)]
 pub extern "C" fn rust64_start(x0: *const u8) -> ! {
     arch::aarch64::simd::setup_simd();
     arch::aarch64::paging::setup();
 
     // Use atomic operation before MMU enabled may cause exception, see https://www.ipshop.xyz/5909.html
+    unsafe { xen::detect::init_fdt(x0) };
     console::init();
     logger::init();
 
@@ -72,14 +76,18 @@ pub extern "C" fn rust64_start(x0: *const u8) -> ! {
 
     unsafe { xrtf_main(&info) };
 
+    #[cfg(feature = "hypercall-stats")]
+    xen::hypercall::stats::dump();
+
     delay::stop_cpu()
 }
 
-#[cfg(target_arch = "riscv64")]
-#[no_mangle]
+#[cfg(all(target_arch = "riscv64", not(test)))]
+#[unsafe(no_mangle)]
 pub extern "C" fn rust64_start(a0: u64, a1: *const u8) -> ! {
     use crate::bootinfo::{EntryType, Info, MemoryEntry};
 
+    unsafe { xen::detect::init_fdt(a1) };
     console::init();
     logger::init();
 
@@ -108,9 +116,13 @@ pub extern "C" fn rust64_start(a0: u64, a1: *const u8) -> ! {
 
     unsafe { xrtf_main(&info) };
 
+    #[cfg(feature = "hypercall-stats")]
+    xen::hypercall::stats::dump();
+
     delay::stop_cpu()
 }
 
+#[cfg(not(test))]
 #[allow(improper_ctypes)]
 unsafe extern "C" {
     fn xrtf_main(info: &dyn bootinfo::Info);
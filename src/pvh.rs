@@ -1,8 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright 2020 Google LLC
 
-use core::mem::size_of;
-
 use crate::{
     bootinfo::{EntryType, Info, MemoryEntry},
     common,
@@ -93,18 +91,23 @@ impl Info for StartInfo {
 }
 
 // The PVH Boot Protocol starts at the 32-bit entrypoint to our firmware.
+#[cfg(not(test))]
 unsafe extern "C" {
     fn ram32_start();
 }
 
 // The kind/name/desc of the PHV ELF Note are from xen/include/public/elfnote.h.
 // This is the "Physical entry point into the kernel".
+#[cfg(not(test))]
 const XEN_ELFNOTE_PHYS32_ENTRY: u32 = 18;
+#[cfg(not(test))]
 type Name = [u8; 4];
+#[cfg(not(test))]
 type Desc = unsafe extern "C" fn();
 
 // We make sure our ELF Note has an alignment of 4 for maximum compatibility.
 // Some software (QEMU) calculates padding incorectly if alignment != 4.
+#[cfg(not(test))]
 #[repr(C, packed(4))]
 struct Note {
     name_size: u32,
@@ -115,6 +118,7 @@ struct Note {
 }
 
 // This is: ELFNOTE(Xen, XEN_ELFNOTE_PHYS32_ENTRY, .quad ram32_start)
+#[cfg(not(test))]
 #[unsafe(link_section = ".note")]
 #[used]
 static PVH_NOTE: Note = Note {
@@ -0,0 +1,183 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Xen presence detection
+//!
+//! Issuing a hypercall outside of Xen raises #UD (x86) or an undefined
+//! instruction exception (Arm), so every Xen subsystem must check for the
+//! hypervisor first.
+
+use atomic_refcell::AtomicRefCell;
+
+#[cfg(target_arch = "x86_64")]
+bitflags::bitflags! {
+    /// HVM specific features (CPUID leaf base+4 EAX)
+    #[derive(Clone, Copy, Debug)]
+    pub struct HvmFeatures: u32 {
+        const APIC_ACCESS_VIRT = 1 << 0;
+        const X2APIC_VIRT = 1 << 1;
+        const IOMMU_MAPPINGS = 1 << 2;
+        const VCPU_ID_PRESENT = 1 << 3;
+        const DOMID_PRESENT = 1 << 4;
+        const UPCALL_VECTOR = 1 << 5;
+    }
+}
+
+/// Informations about the Xen hypervisor we are running on.
+#[derive(Clone, Copy, Debug)]
+pub struct XenInfo {
+    /// Hypervisor version as (major, minor), (0, 0) if unknown.
+    pub version: (u16, u16),
+    /// First Xen CPUID leaf (0x4000_0000 + n * 0x100).
+    #[cfg(target_arch = "x86_64")]
+    pub cpuid_base: u32,
+    /// Last valid Xen CPUID leaf.
+    #[cfg(target_arch = "x86_64")]
+    pub cpuid_max_leaf: u32,
+    /// Number of hypercall pages (leaf base+2 EAX).
+    #[cfg(target_arch = "x86_64")]
+    pub hypercall_pages: u32,
+    /// MSR used to register the hypercall page (leaf base+2 EBX).
+    #[cfg(target_arch = "x86_64")]
+    pub hypercall_msr: u32,
+    /// HVM features (leaf base+4 EAX), empty if the leaf is missing.
+    #[cfg(target_arch = "x86_64")]
+    pub hvm_features: HvmFeatures,
+    /// Our vCPU id, if advertised.
+    #[cfg(target_arch = "x86_64")]
+    pub vcpu_id: Option<u32>,
+    /// Our domain id, if advertised.
+    #[cfg(target_arch = "x86_64")]
+    pub domid: Option<u16>,
+    /// Region reserved for the grant table (first `reg` of `/hypervisor`).
+    #[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+    pub grant_table: Option<(u64, usize)>,
+}
+
+#[derive(Clone, Copy)]
+enum Detection {
+    Pending,
+    Done(Option<XenInfo>),
+}
+
+static XEN_INFO: AtomicRefCell<Detection> = AtomicRefCell::new(Detection::Pending);
+
+/// `__cpuid` is only unsafe on older toolchains.
+#[cfg(target_arch = "x86_64")]
+fn cpuid(leaf: u32) -> core::arch::x86_64::CpuidResult {
+    #[allow(unused_unsafe)]
+    unsafe {
+        core::arch::x86_64::__cpuid(leaf)
+    }
+}
+
+#[cfg(target_arch = "x86_64")]
+fn probe() -> Option<XenInfo> {
+    // Xen may be moved to a further leaf (e.g. when Viridian is enabled).
+    let (cpuid_base, cpuid_max_leaf) =
+        (0x4000_0000..0x4001_0000).step_by(0x100).find_map(|base| {
+            let leaf = cpuid(base);
+            let signature = [leaf.ebx, leaf.ecx, leaf.edx];
+
+            (signature.map(u32::to_ne_bytes).as_flattened() == b"XenVMMXenVMM"
+                && leaf.eax >= base + 2)
+                .then_some((base, leaf.eax))
+        })?;
+
+    let version = cpuid(cpuid_base + 1).eax;
+    let hypercall_leaf = cpuid(cpuid_base + 2);
+
+    let mut info = XenInfo {
+        version: ((version >> 16) as u16, version as u16),
+        cpuid_base,
+        cpuid_max_leaf,
+        hypercall_pages: hypercall_leaf.eax,
+        hypercall_msr: hypercall_leaf.ebx,
+        hvm_features: HvmFeatures::empty(),
+        vcpu_id: None,
+        domid: None,
+    };
+
+    if cpuid_max_leaf >= cpuid_base + 4 {
+        let hvm_leaf = cpuid(cpuid_base + 4);
+        info.hvm_features = HvmFeatures::from_bits_truncate(hvm_leaf.eax);
+
+        if info.hvm_features.contains(HvmFeatures::VCPU_ID_PRESENT) {
+            info.vcpu_id = Some(hvm_leaf.ebx);
+        }
+
+        if info.hvm_features.contains(HvmFeatures::DOMID_PRESENT) {
+            info.domid = Some(hvm_leaf.ecx as u16);
+        }
+    }
+
+    Some(info)
+}
+
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+fn probe_fdt(fdt: &fdt::Fdt) -> Option<XenInfo> {
+    let node = fdt.find_node("/hypervisor")?;
+    let compatible = node.compatible()?;
+
+    if !compatible.all().any(|c| c == "xen,xen") {
+        return None;
+    }
+
+    // The version is advertised as "xen,xen-<major>.<minor>".
+    let version = compatible
+        .all()
+        .find_map(|c| {
+            let (major, minor) = c.strip_prefix("xen,xen-")?.split_once('.')?;
+            Some((major.parse().ok()?, minor.parse().ok()?))
+        })
+        .unwrap_or((0, 0));
+
+    let grant_table = node
+        .reg()
+        .and_then(|mut reg| reg.next())
+        .and_then(|region| Some((region.starting_address as u64, region.size?)));
+
+    Some(XenInfo {
+        version,
+        grant_table,
+    })
+}
+
+/// Look for Xen in the device tree, must be done before [`detect`].
+///
+/// # Safety
+///
+/// `ptr` must point to a valid flattened device tree.
+#[cfg(any(target_arch = "aarch64", target_arch = "riscv64"))]
+pub unsafe fn init_fdt(ptr: *const u8) {
+    let info = unsafe { fdt::Fdt::from_ptr(ptr) }
+        .ok()
+        .and_then(|fdt| probe_fdt(&fdt));
+
+    *XEN_INFO.borrow_mut() = Detection::Done(info);
+}
+
+/// Check whether we are running on Xen.
+///
+/// On x86, the CPUID leaves are probed on first call. On FDT platforms,
+/// [`init_fdt`] must have been called first, otherwise Xen is assumed
+/// to be missing.
+pub fn detect() -> Option<XenInfo> {
+    if let Detection::Done(info) = *XEN_INFO.borrow() {
+        return info;
+    }
+
+    #[cfg(target_arch = "x86_64")]
+    let info = probe();
+
+    #[cfg(not(target_arch = "x86_64"))]
+    let info = None;
+
+    *XEN_INFO.borrow_mut() = Detection::Done(info);
+    info
+}
+
+/// Shorthand for `detect().is_some()`.
+pub fn is_present() -> bool {
+    detect().is_some()
+}
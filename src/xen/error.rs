@@ -0,0 +1,144 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Xen hypercall error values (xen/include/public/errno.h)
+
+use core::fmt;
+
+/// Highest errno value a hypercall may return (same bound as Linux IS_ERR_VALUE).
+const MAX_ERRNO: isize = 4095;
+
+macro_rules! xen_errors {
+    ($($variant:ident = $errno:literal, $name:literal, $desc:literal;)*) => {
+        /// Error returned by a Xen hypercall.
+        ///
+        /// Xen reports failures as a negated errno value, using its own
+        /// numbering (which matches the Linux one).
+        #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+        pub enum XenError {
+            $(
+                #[doc = concat!($name, ": ", $desc)]
+                $variant,
+            )*
+            /// Errno value unknown to this version of the interface
+            Unknown(i32),
+        }
+
+        impl XenError {
+            /// Build an error from a positive errno value.
+            pub fn from_errno(errno: i32) -> Self {
+                match errno {
+                    $($errno => XenError::$variant,)*
+                    errno => XenError::Unknown(errno),
+                }
+            }
+
+            /// Build an error from its symbolic name (e.g. "EAGAIN").
+            pub fn from_name(name: &str) -> Option<Self> {
+                match name {
+                    $($name => Some(XenError::$variant),)*
+                    _ => None,
+                }
+            }
+
+            /// Positive errno value of this error.
+            pub fn errno(&self) -> i32 {
+                match self {
+                    $(XenError::$variant => $errno,)*
+                    XenError::Unknown(errno) => *errno,
+                }
+            }
+
+            /// Symbolic name of this error (e.g. "EAGAIN").
+            pub fn name(&self) -> &'static str {
+                match self {
+                    $(XenError::$variant => $name,)*
+                    XenError::Unknown(_) => "EUNKNOWN",
+                }
+            }
+
+            fn description(&self) -> &'static str {
+                match self {
+                    $(XenError::$variant => $desc,)*
+                    XenError::Unknown(_) => "Unknown error",
+                }
+            }
+        }
+    };
+}
+
+xen_errors! {
+    Perm = 1, "EPERM", "Operation not permitted";
+    NoEnt = 2, "ENOENT", "No such file or directory";
+    Srch = 3, "ESRCH", "No such process";
+    Intr = 4, "EINTR", "Interrupted system call";
+    Io = 5, "EIO", "I/O error";
+    NxIo = 6, "ENXIO", "No such device or address";
+    TooBig = 7, "E2BIG", "Arg list too long";
+    NoExec = 8, "ENOEXEC", "Exec format error";
+    BadF = 9, "EBADF", "Bad file number";
+    Child = 10, "ECHILD", "No child processes";
+    Again = 11, "EAGAIN", "Try again";
+    NoMem = 12, "ENOMEM", "Out of memory";
+    Access = 13, "EACCES", "Permission denied";
+    Fault = 14, "EFAULT", "Bad address";
+    Busy = 16, "EBUSY", "Device or resource busy";
+    Exist = 17, "EEXIST", "File exists";
+    XDev = 18, "EXDEV", "Cross-device link";
+    NoDev = 19, "ENODEV", "No such device";
+    NotDir = 20, "ENOTDIR", "Not a directory";
+    IsDir = 21, "EISDIR", "Is a directory";
+    Inval = 22, "EINVAL", "Invalid argument";
+    NFile = 23, "ENFILE", "File table overflow";
+    MFile = 24, "EMFILE", "Too many open files";
+    NoSpc = 28, "ENOSPC", "No space left on device";
+    SPipe = 29, "ESPIPE", "Illegal seek";
+    RoFs = 30, "EROFS", "Read-only file system";
+    MLink = 31, "EMLINK", "Too many links";
+    Dom = 33, "EDOM", "Math argument out of domain of func";
+    Range = 34, "ERANGE", "Math result not representable";
+    DeadLk = 35, "EDEADLK", "Resource deadlock would occur";
+    NameTooLong = 36, "ENAMETOOLONG", "File name too long";
+    NoLck = 37, "ENOLCK", "No record locks available";
+    NoSys = 38, "ENOSYS", "Function not implemented";
+    NotEmpty = 39, "ENOTEMPTY", "Directory not empty";
+    NoData = 61, "ENODATA", "No data available";
+    Time = 62, "ETIME", "Timer expired";
+    BadMsg = 74, "EBADMSG", "Not a data message";
+    Overflow = 75, "EOVERFLOW", "Value too large for defined data type";
+    IlSeq = 84, "EILSEQ", "Illegal byte sequence";
+    Restart = 85, "ERESTART", "Interrupted system call should be restarted";
+    NotSock = 88, "ENOTSOCK", "Socket operation on non-socket";
+    MsgSize = 90, "EMSGSIZE", "Message too large";
+    OpNotSupp = 95, "EOPNOTSUPP", "Operation not supported on transport endpoint";
+    AddrInUse = 98, "EADDRINUSE", "Address already in use";
+    AddrNotAvail = 99, "EADDRNOTAVAIL", "Cannot assign requested address";
+    NoBufs = 105, "ENOBUFS", "No buffer space available";
+    IsConn = 106, "EISCONN", "Transport endpoint is already connected";
+    NotConn = 107, "ENOTCONN", "Transport endpoint is not connected";
+    TimedOut = 110, "ETIMEDOUT", "Connection timed out";
+    ConnRefused = 111, "ECONNREFUSED", "Connection refused";
+}
+
+impl XenError {
+    /// Split a raw hypercall return value into a result or an error.
+    #[inline(always)]
+    pub fn from_ret(ret: usize) -> Result<usize, XenError> {
+        let errno = (ret as isize).wrapping_neg();
+
+        if (1..=MAX_ERRNO).contains(&errno) {
+            Err(Self::from_errno(errno as i32))
+        } else {
+            Ok(ret)
+        }
+    }
+}
+
+impl fmt::Display for XenError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            XenError::Unknown(errno) => write!(f, "Unknown error {errno}"),
+            e => write!(f, "{} ({})", e.description(), e.name()),
+        }
+    }
+}
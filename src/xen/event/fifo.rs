@@ -0,0 +1,356 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! FIFO event channel ABI
+//!
+//! Each port has an event word in the event array, pending events are linked
+//! by Xen into one queue per priority. The control block tells which queues
+//! are ready along with their head, and the queues are consumed by following
+//! the link of each event word.
+//!
+//! The control block and the event array live in static pages, which bounds
+//! the number of usable ports to [`NR_EVENT_CHANNELS`].
+
+use core::{
+    cell::SyncUnsafeCell,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicBool, AtomicPtr, AtomicU32, AtomicUsize, Ordering},
+};
+
+use super::{EVENT_CHANNEL_OP, unmask as unmask_port};
+use crate::{
+    arch::map_4k_frame,
+    xen::{
+        XenError,
+        hypercall::{call, xen_op},
+        shared_info::VcpuInfo,
+    },
+};
+
+const EVTCHNOP_INIT_CONTROL: usize = 11;
+const EVTCHNOP_EXPAND_ARRAY: usize = 12;
+const EVTCHNOP_SET_PRIORITY: usize = 13;
+
+pub const EVTCHN_FIFO_PENDING: u32 = 1 << 31;
+pub const EVTCHN_FIFO_MASKED: u32 = 1 << 30;
+pub const EVTCHN_FIFO_LINKED: u32 = 1 << 29;
+pub const EVTCHN_FIFO_BUSY: u32 = 1 << 28;
+
+pub const EVTCHN_FIFO_LINK_BITS: u32 = 17;
+pub const EVTCHN_FIFO_LINK_MASK: u32 = (1 << EVTCHN_FIFO_LINK_BITS) - 1;
+
+pub const EVTCHN_FIFO_PRIORITY_MAX: u32 = 0;
+pub const EVTCHN_FIFO_PRIORITY_DEFAULT: u32 = 7;
+pub const EVTCHN_FIFO_PRIORITY_MIN: u32 = 15;
+pub const EVTCHN_FIFO_MAX_QUEUES: usize = EVTCHN_FIFO_PRIORITY_MIN as usize + 1;
+
+/// Number of event words in an event array page.
+pub const EVENT_WORDS_PER_PAGE: usize = 4096 / size_of::<u32>();
+
+/// Number of event array pages given to Xen.
+pub const EVENT_ARRAY_PAGES: usize = 8;
+
+/// Number of ports usable with the static event array.
+pub const NR_EVENT_CHANNELS: usize = EVENT_ARRAY_PAGES * EVENT_WORDS_PER_PAGE;
+
+/// struct evtchn_fifo_control_block
+#[repr(C)]
+pub struct ControlBlock {
+    /// One bit per non-empty queue.
+    pub ready: AtomicU32,
+    _rsvd: u32,
+    /// First event of each queue.
+    pub head: [AtomicU32; EVTCHN_FIFO_MAX_QUEUES],
+}
+
+pub type EventArrayPage = [AtomicU32; EVENT_WORDS_PER_PAGE];
+
+#[repr(C, align(4096))]
+struct Page([u8; 4096]);
+
+static CONTROL_PAGE: SyncUnsafeCell<Page> = SyncUnsafeCell::new(Page([0; _]));
+static EVENT_ARRAY_PAGE: [SyncUnsafeCell<Page>; EVENT_ARRAY_PAGES] =
+    [const { SyncUnsafeCell::new(Page([0; _])) }; _];
+
+static CONTROL_BLOCK: AtomicPtr<ControlBlock> = AtomicPtr::new(ptr::null_mut());
+static EVENT_ARRAY: [AtomicPtr<EventArrayPage>; EVENT_ARRAY_PAGES] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; _];
+/// Number of event array pages accepted by Xen.
+static EVENT_ARRAY_LEN: AtomicUsize = AtomicUsize::new(0);
+static FIFO_ENABLED: AtomicBool = AtomicBool::new(false);
+/// Set once EVTCHNOP_init_control succeeded, Xen no longer uses the 2-level ABI.
+static FIFO_SWITCHED: AtomicBool = AtomicBool::new(false);
+
+/// Next event of each queue, 0 once the tail has been reached.
+static QUEUE_HEAD: [AtomicU32; EVTCHN_FIFO_MAX_QUEUES] = [const { AtomicU32::new(0) }; _];
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnInitControl(EVENT_CHANNEL_OP) {
+        control_gfn: u64 => in(0),
+        offset: u32 => in(1),
+        vcpu: u32 => in(2),
+        link_bits: u8 => out(3),
+        _pad: [u8; 7],
+    }
+}
+
+xen_op! {
+    struct EvtchnExpandArray(EVENT_CHANNEL_OP) {
+        array_gfn: u64 => in(0),
+    }
+}
+
+xen_op! {
+    struct EvtchnSetPriority(EVENT_CHANNEL_OP) {
+        port: u32 => in(0),
+        priority: u32 => in(1),
+    }
+}
+
+/// Map `page` as shared memory and clear it.
+fn map_page<T>(page: &SyncUnsafeCell<Page>) -> Result<(u64, NonNull<T>), XenError> {
+    let pfn = (page.get().addr() >> 12) as u64;
+    let ptr = map_4k_frame::<Page>(pfn, false).ok_or(XenError::NoMem)?;
+
+    // The page may have been cleared through an encrypted mapping.
+    unsafe { ptr.write_bytes(0, 1) };
+
+    Ok((pfn, ptr.cast()))
+}
+
+/// Switch to the FIFO ABI for vCPU `vcpu`, and give the event array to Xen.
+///
+/// Fails if Xen doesn't support it, in which case the 2-level ABI stays
+/// in use, unless [`is_switched`] tells Xen already switched to FIFO.
+pub fn init(vcpu: u32) -> Result<(), XenError> {
+    if FIFO_ENABLED.load(Ordering::Acquire) {
+        return Ok(());
+    }
+
+    let (control_gfn, control_block) = map_page::<ControlBlock>(&CONTROL_PAGE)?;
+
+    let mut pages = [(0, NonNull::dangling()); EVENT_ARRAY_PAGES];
+    for (page, mapping) in EVENT_ARRAY_PAGE.iter().zip(&mut pages) {
+        *mapping = map_page::<EventArrayPage>(page)?;
+    }
+
+    let mut init_control = EvtchnInitControl {
+        control_gfn,
+        offset: 0,
+        vcpu,
+        ..Default::default()
+    };
+
+    unsafe { call(EVTCHNOP_INIT_CONTROL, &mut init_control) }?;
+    FIFO_SWITCHED.store(true, Ordering::Release);
+
+    // Xen is now using the FIFO ABI, keep the pages it accepted even if it
+    // refuses some.
+    for (index, (array_gfn, page)) in pages.into_iter().enumerate() {
+        let mut expand_array = EvtchnExpandArray { array_gfn };
+
+        if let Err(e) = unsafe { call(EVTCHNOP_EXPAND_ARRAY, &mut expand_array) } {
+            if index == 0 {
+                // No port is usable without the first page. Going back to
+                // the 2-level ABI would need EVTCHNOP_reset, which closes
+                // ports bound by others (e.g. console), so give up as Linux
+                // does.
+                log::error!("Xen refused the first FIFO event array page: {e}");
+                return Err(e);
+            }
+
+            log::warn!("Unable to expand the FIFO event array past {index} pages: {e}");
+            break;
+        }
+
+        EVENT_ARRAY[index].store(page.as_ptr(), Ordering::Release);
+        EVENT_ARRAY_LEN.store(index + 1, Ordering::Release);
+    }
+
+    CONTROL_BLOCK.store(control_block.as_ptr(), Ordering::Release);
+    QUEUE_HEAD
+        .iter()
+        .for_each(|head| head.store(0, Ordering::Relaxed));
+    FIFO_ENABLED.store(true, Ordering::Release);
+
+    log::info!(
+        "Using FIFO event channels ({} ports, {} link bits)",
+        nr_ports(),
+        init_control.link_bits
+    );
+
+    Ok(())
+}
+
+/// Whether the FIFO ABI is in use.
+pub fn is_enabled() -> bool {
+    FIFO_ENABLED.load(Ordering::Acquire)
+}
+
+/// Whether Xen has switched to the FIFO ABI, even if [`init`] then failed.
+pub fn is_switched() -> bool {
+    FIFO_SWITCHED.load(Ordering::Acquire)
+}
+
+/// Number of ports covered by the event array.
+pub fn nr_ports() -> usize {
+    EVENT_ARRAY_LEN.load(Ordering::Acquire) * EVENT_WORDS_PER_PAGE
+}
+
+/// Event word of `port`, if covered by the event array.
+pub fn event_word(port: u32) -> Option<&'static AtomicU32> {
+    let port = port as usize;
+    let page = EVENT_ARRAY.get(port / EVENT_WORDS_PER_PAGE)?;
+
+    // SAFETY: Only ever set to a mapped event array page.
+    let page = unsafe { page.load(Ordering::Acquire).as_ref() }?;
+
+    Some(&page[port % EVENT_WORDS_PER_PAGE])
+}
+
+pub fn is_pending(word: &AtomicU32) -> bool {
+    word.load(Ordering::Acquire) & EVTCHN_FIFO_PENDING != 0
+}
+
+pub fn is_masked(word: &AtomicU32) -> bool {
+    word.load(Ordering::Acquire) & EVTCHN_FIFO_MASKED != 0
+}
+
+pub fn clear(word: &AtomicU32) {
+    word.fetch_and(!EVTCHN_FIFO_PENDING, Ordering::AcqRel);
+}
+
+pub fn mask(word: &AtomicU32) {
+    word.fetch_or(EVTCHN_FIFO_MASKED, Ordering::AcqRel);
+}
+
+/// Clear MASKED unless the event is pending, as Xen then needs to link it.
+///
+/// Returns false if Xen must unmask the event.
+fn clear_masked_cond(word: &AtomicU32) -> bool {
+    let mut current = word.load(Ordering::Acquire);
+
+    loop {
+        if current & EVTCHN_FIFO_MASKED == 0 {
+            return true;
+        }
+
+        if current & EVTCHN_FIFO_PENDING != 0 {
+            return false;
+        }
+
+        // Xen may hold BUSY while linking, wait for it to be released.
+        let old = current & !EVTCHN_FIFO_BUSY;
+
+        match word.compare_exchange(
+            old,
+            old & !EVTCHN_FIFO_MASKED,
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return true,
+            Err(value) => current = value,
+        }
+    }
+}
+
+pub fn unmask(port: u32, word: &AtomicU32) -> Result<(), XenError> {
+    if clear_masked_cond(word) {
+        Ok(())
+    } else {
+        unmask_port(port)
+    }
+}
+
+pub fn set_priority(port: u32, priority: u32) -> Result<(), XenError> {
+    let mut set_priority = EvtchnSetPriority { port, priority };
+
+    unsafe { call(EVTCHNOP_SET_PRIORITY, &mut set_priority) }?;
+
+    Ok(())
+}
+
+/// Clear LINKED and the link of `word`, returns the link.
+fn clear_linked(word: &AtomicU32) -> u32 {
+    let mut current = word.load(Ordering::Acquire);
+
+    loop {
+        match word.compare_exchange(
+            current,
+            current & !(EVTCHN_FIFO_LINKED | EVTCHN_FIFO_LINK_MASK),
+            Ordering::AcqRel,
+            Ordering::Acquire,
+        ) {
+            Ok(_) => return current & EVTCHN_FIFO_LINK_MASK,
+            Err(value) => current = value,
+        }
+    }
+}
+
+/// Consume the head of queue `priority`.
+fn consume_one(
+    control_block: &ControlBlock,
+    priority: usize,
+    ready: &mut u32,
+    handle: &mut impl FnMut(u32),
+) {
+    let mut head = QUEUE_HEAD[priority].load(Ordering::Relaxed);
+
+    // Reached the tail last time, Xen may have queued new events since.
+    if head == 0 {
+        head = control_block.head[priority].load(Ordering::Acquire);
+    }
+
+    let port = head;
+    let Some(word) = event_word(port) else {
+        // Link to a port outside of the event array, give up on the queue.
+        *ready &= !(1 << priority);
+        QUEUE_HEAD[priority].store(0, Ordering::Relaxed);
+        return;
+    };
+
+    let next = clear_linked(word);
+
+    // A zero link marks the tail.
+    if next == 0 {
+        *ready &= !(1 << priority);
+    }
+
+    if is_pending(word) && !is_masked(word) {
+        clear(word);
+        handle(port);
+    }
+
+    QUEUE_HEAD[priority].store(next, Ordering::Relaxed);
+}
+
+/// Consume the ready queues in priority order and give the pending and
+/// unmasked ports to `handle`, with the port cleared.
+///
+/// Returns the number of handled events.
+pub fn scan(vcpu: &VcpuInfo, mut handle: impl FnMut(u32)) -> usize {
+    // SAFETY: Only ever set to the mapped control block.
+    let Some(control_block) = (unsafe { CONTROL_BLOCK.load(Ordering::Acquire).as_ref() }) else {
+        return 0;
+    };
+
+    let mut handled = 0;
+
+    vcpu.evtchn_upcall_pending.store(0, Ordering::Release);
+
+    let mut ready = control_block.ready.swap(0, Ordering::AcqRel);
+
+    while ready != 0 {
+        let priority = ready.trailing_zeros() as usize;
+
+        consume_one(control_block, priority, &mut ready, &mut |port| {
+            handled += 1;
+            handle(port)
+        });
+
+        ready |= control_block.ready.swap(0, Ordering::AcqRel);
+    }
+
+    handled
+}
@@ -0,0 +1,490 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Event channels
+//!
+//! Pending events are found through the shared info page (2-level ABI) or
+//! the FIFO queues, and dispatched to the handler registered for their port.
+//! Only the boot vCPU is used, which is where Xen binds ports by default.
+
+use core::{
+    mem,
+    ops::Deref,
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+};
+
+use crate::xen::{
+    DomId, XenError,
+    hypercall::{call, xen_op},
+    shared_info::{self, SharedInfo},
+};
+
+pub mod fifo;
+pub mod two_level;
+#[cfg(target_arch = "x86_64")]
+pub mod upcall;
+
+/// Handle to a port, which may not be owned by us (e.g. the console one).
+///
+/// See [`OwnedEventChannel`] for ports we allocate.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct EventChannel(pub u32);
+
+/// VIRQ_* values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum Virq {
+    /// Timer tick (see set_timer_op)
+    Timer = 0,
+    Debug = 1,
+    /// Console input (dom0 only)
+    Console = 2,
+    DomExc = 3,
+    Tbuf = 4,
+    Debugger = 6,
+    Xenoprof = 7,
+    ConRing = 8,
+    PcpuState = 9,
+    MemEvent = 10,
+    Argo = 11,
+    Enomem = 12,
+    Xenpmu = 13,
+}
+
+/// State of a port (EVTCHNSTAT_*).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventChannelState {
+    Closed,
+    /// Waiting for `remote` to bind it.
+    Unbound {
+        remote: DomId,
+    },
+    Interdomain {
+        remote: DomId,
+        remote_port: u32,
+    },
+    Pirq(u32),
+    Virq(u32),
+    Ipi,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct EventChannelStatus {
+    pub state: EventChannelState,
+    /// vCPU notified of the events.
+    pub vcpu: u32,
+}
+
+/// Called with the port of each pending event, with the port cleared.
+pub type EventHandler = fn(EventChannel);
+
+/// Event channel ABI in use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EventAbi {
+    TwoLevel,
+    Fifo,
+}
+
+/// Highest number of ports we may handle, whatever the ABI.
+pub const MAX_EVENT_CHANNELS: usize = if fifo::NR_EVENT_CHANNELS > two_level::NR_EVENT_CHANNELS {
+    fifo::NR_EVENT_CHANNELS
+} else {
+    two_level::NR_EVENT_CHANNELS
+};
+
+const EVENT_CHANNEL_OP: usize = 32;
+
+const EVTCHNOP_BIND_INTERDOMAIN: usize = 0;
+const EVTCHNOP_BIND_VIRQ: usize = 1;
+const EVTCHNOP_CLOSE: usize = 3;
+const EVTCHNOP_SEND: usize = 4;
+const EVTCHNOP_STATUS: usize = 5;
+const EVTCHNOP_ALLOC_UNBOUND: usize = 6;
+const EVTCHNOP_BIND_IPI: usize = 7;
+const EVTCHNOP_BIND_VCPU: usize = 8;
+const EVTCHNOP_UNMASK: usize = 9;
+
+const EVTCHNSTAT_CLOSED: u32 = 0;
+const EVTCHNSTAT_UNBOUND: u32 = 1;
+const EVTCHNSTAT_INTERDOMAIN: u32 = 2;
+const EVTCHNSTAT_PIRQ: u32 = 3;
+const EVTCHNSTAT_VIRQ: u32 = 4;
+const EVTCHNSTAT_IPI: u32 = 5;
+
+/// vCPU receiving the events.
+const VCPU: usize = 0;
+
+static HANDLERS: [AtomicPtr<()>; MAX_EVENT_CHANNELS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; _];
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnBindInterdomain(EVENT_CHANNEL_OP) {
+        remote_dom: u16 => in(0),
+        remote_port: u32 => in(1),
+        local_port: u32 => out(2),
+    }
+}
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnBindVirq(EVENT_CHANNEL_OP) {
+        virq: u32 => in(0),
+        vcpu: u32 => in(1),
+        port: u32 => out(2),
+    }
+}
+
+xen_op! {
+    struct EvtchnClose(EVENT_CHANNEL_OP) {
+        port: u32 => in(0),
+    }
+}
+
+xen_op! {
+    struct EvtchnSend(EVENT_CHANNEL_OP) {
+        port: u32 => in(0),
+    }
+}
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnStatus(EVENT_CHANNEL_OP) {
+        dom: u16 => in(0),
+        port: u32 => in(1),
+        status: u32 => out(2),
+        vcpu: u32 => out(3),
+        /// Union of unbound.dom, interdomain.{dom, port}, pirq and virq.
+        u: [u32; 2],
+    }
+}
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnAllocUnbound(EVENT_CHANNEL_OP) {
+        dom: u16 => in(0),
+        remote_dom: u16 => in(1),
+        port: u32 => out(2),
+    }
+}
+
+xen_op! {
+    #[derive(Default)]
+    struct EvtchnBindIpi(EVENT_CHANNEL_OP) {
+        vcpu: u32 => in(0),
+        port: u32 => out(1),
+    }
+}
+
+xen_op! {
+    struct EvtchnBindVcpu(EVENT_CHANNEL_OP) {
+        port: u32 => in(0),
+        vcpu: u32 => in(1),
+    }
+}
+
+xen_op! {
+    struct EvtchnUnmask(EVENT_CHANNEL_OP) {
+        port: u32 => in(0),
+    }
+}
+
+fn close(port: EventChannel) -> Result<(), XenError> {
+    port.set_handler(None).ok();
+
+    let mut op = EvtchnClose { port: port.0 };
+
+    unsafe { call(EVTCHNOP_CLOSE, &mut op) }?;
+
+    Ok(())
+}
+
+fn unmask(port: u32) -> Result<(), XenError> {
+    let mut unmask = EvtchnUnmask { port };
+
+    unsafe { call(EVTCHNOP_UNMASK, &mut unmask) }?;
+
+    Ok(())
+}
+
+/// Map the shared info page and try to switch to the FIFO ABI, falling back
+/// to the 2-level one if Xen doesn't support it.
+pub fn init() -> Result<EventAbi, XenError> {
+    shared_info::map()?;
+
+    match fifo::init(VCPU as u32) {
+        Ok(()) => {}
+        // Events are no longer delivered through the 2-level bitmaps.
+        Err(e) if fifo::is_switched() => return Err(e),
+        Err(e) => log::info!("FIFO event channels unavailable ({e}), using 2-level"),
+    }
+
+    Ok(abi())
+}
+
+pub fn abi() -> EventAbi {
+    if fifo::is_enabled() {
+        EventAbi::Fifo
+    } else {
+        EventAbi::TwoLevel
+    }
+}
+
+/// Number of ports usable with the current ABI.
+pub fn nr_ports() -> usize {
+    match abi() {
+        EventAbi::TwoLevel => two_level::NR_EVENT_CHANNELS,
+        EventAbi::Fifo => fifo::nr_ports(),
+    }
+}
+
+fn handle(port: u32) {
+    let Some(handler) = HANDLERS.get(port as usize) else {
+        return;
+    };
+
+    let handler = handler.load(Ordering::Acquire);
+
+    if !handler.is_null() {
+        // SAFETY: HANDLERS only holds null or EventHandler pointers.
+        let handler = unsafe { mem::transmute::<*mut (), EventHandler>(handler) };
+        handler(EventChannel(port));
+    }
+}
+
+/// Handle all the pending events, returns the number of events seen.
+///
+/// Events without a handler are cleared and dropped.
+pub fn dispatch() -> Result<usize, XenError> {
+    let shared = shared_info::map()?;
+    let vcpu = &shared.vcpu_info[VCPU];
+
+    Ok(match abi() {
+        EventAbi::TwoLevel => two_level::scan(shared, vcpu, handle),
+        EventAbi::Fifo => fifo::scan(vcpu, handle),
+    })
+}
+
+/// Per-ABI state of a port.
+enum Port {
+    TwoLevel(&'static SharedInfo),
+    Fifo(&'static AtomicU32),
+}
+
+impl EventChannel {
+    fn port(&self) -> Result<Port, XenError> {
+        let shared = shared_info::map()?;
+
+        match abi() {
+            EventAbi::TwoLevel if (self.0 as usize) < two_level::NR_EVENT_CHANNELS => {
+                Ok(Port::TwoLevel(shared))
+            }
+            EventAbi::Fifo => fifo::event_word(self.0)
+                .map(Port::Fifo)
+                .ok_or(XenError::Inval),
+            _ => Err(XenError::Inval),
+        }
+    }
+
+    pub fn send(&self) -> Result<(), XenError> {
+        let mut evtchn_send = EvtchnSend { port: self.0 };
+
+        unsafe { call(EVTCHNOP_SEND, &mut evtchn_send) }?;
+
+        Ok(())
+    }
+
+    /// Set (or remove) the handler called by [`dispatch`] for this port.
+    pub fn set_handler(&self, handler: Option<EventHandler>) -> Result<(), XenError> {
+        let slot = HANDLERS.get(self.0 as usize).ok_or(XenError::Inval)?;
+
+        slot.store(
+            handler.map_or(ptr::null_mut(), |handler| handler as *mut ()),
+            Ordering::Release,
+        );
+
+        Ok(())
+    }
+
+    pub fn is_pending(&self) -> Result<bool, XenError> {
+        Ok(match self.port()? {
+            Port::TwoLevel(shared) => two_level::is_pending(shared, self.0),
+            Port::Fifo(word) => fifo::is_pending(word),
+        })
+    }
+
+    pub fn clear(&self) -> Result<(), XenError> {
+        match self.port()? {
+            Port::TwoLevel(shared) => two_level::clear(shared, self.0),
+            Port::Fifo(word) => fifo::clear(word),
+        }
+
+        Ok(())
+    }
+
+    pub fn mask(&self) -> Result<(), XenError> {
+        match self.port()? {
+            Port::TwoLevel(shared) => two_level::mask(shared, self.0),
+            Port::Fifo(word) => fifo::mask(word),
+        }
+
+        Ok(())
+    }
+
+    /// Unmask the port (EVTCHNOP_unmask), an event received while masked
+    /// is notified again.
+    pub fn unmask(&self) -> Result<(), XenError> {
+        match self.port()? {
+            Port::TwoLevel(_) => unmask(self.0),
+            Port::Fifo(word) => fifo::unmask(self.0, word),
+        }
+    }
+
+    /// Query the state of the port.
+    pub fn status(&self) -> Result<EventChannelStatus, XenError> {
+        let mut op = EvtchnStatus {
+            dom: DomId::SELF.0,
+            port: self.0,
+            ..Default::default()
+        };
+
+        unsafe { call(EVTCHNOP_STATUS, &mut op) }?;
+
+        let state = match op.status {
+            EVTCHNSTAT_CLOSED => EventChannelState::Closed,
+            EVTCHNSTAT_UNBOUND => EventChannelState::Unbound {
+                remote: DomId(op.u[0] as u16),
+            },
+            EVTCHNSTAT_INTERDOMAIN => EventChannelState::Interdomain {
+                remote: DomId(op.u[0] as u16),
+                remote_port: op.u[1],
+            },
+            EVTCHNSTAT_PIRQ => EventChannelState::Pirq(op.u[0]),
+            EVTCHNSTAT_VIRQ => EventChannelState::Virq(op.u[0]),
+            EVTCHNSTAT_IPI => EventChannelState::Ipi,
+            _ => return Err(XenError::BadMsg),
+        };
+
+        Ok(EventChannelStatus {
+            state,
+            vcpu: op.vcpu,
+        })
+    }
+
+    /// Notify `vcpu` of the events of this port instead.
+    ///
+    /// Only the boot vCPU is handled by [`dispatch`].
+    pub fn bind_vcpu(&self, vcpu: u32) -> Result<(), XenError> {
+        let mut op = EvtchnBindVcpu { port: self.0, vcpu };
+
+        unsafe { call(EVTCHNOP_BIND_VCPU, &mut op) }?;
+
+        Ok(())
+    }
+
+    /// Set the FIFO queue of this port (EVTCHN_FIFO_PRIORITY_*), only
+    /// supported by the FIFO ABI.
+    pub fn set_priority(&self, priority: u32) -> Result<(), XenError> {
+        match abi() {
+            EventAbi::Fifo => fifo::set_priority(self.0, priority),
+            EventAbi::TwoLevel => Err(XenError::OpNotSupp),
+        }
+    }
+}
+
+/// Port allocated by us, closed when dropped.
+#[derive(Debug)]
+pub struct OwnedEventChannel(EventChannel);
+
+impl OwnedEventChannel {
+    /// Allocate a port for `remote` to bind with
+    /// [`OwnedEventChannel::bind_interdomain`].
+    pub fn alloc_unbound(remote: DomId) -> Result<Self, XenError> {
+        let mut op = EvtchnAllocUnbound {
+            dom: DomId::SELF.0,
+            remote_dom: remote.0,
+            ..Default::default()
+        };
+
+        unsafe { call(EVTCHNOP_ALLOC_UNBOUND, &mut op) }?;
+
+        Ok(Self(EventChannel(op.port)))
+    }
+
+    /// Connect to `remote_port`, allocated by `remote` for us.
+    pub fn bind_interdomain(remote: DomId, remote_port: u32) -> Result<Self, XenError> {
+        let mut op = EvtchnBindInterdomain {
+            remote_dom: remote.0,
+            remote_port,
+            ..Default::default()
+        };
+
+        unsafe { call(EVTCHNOP_BIND_INTERDOMAIN, &mut op) }?;
+
+        Ok(Self(EventChannel(op.local_port)))
+    }
+
+    /// Receive `virq` on `vcpu`.
+    pub fn bind_virq(virq: Virq, vcpu: u32) -> Result<Self, XenError> {
+        let mut op = EvtchnBindVirq {
+            virq: virq as u32,
+            vcpu,
+            ..Default::default()
+        };
+
+        unsafe { call(EVTCHNOP_BIND_VIRQ, &mut op) }?;
+
+        Ok(Self(EventChannel(op.port)))
+    }
+
+    /// Allocate a port notifying `vcpu` when sent to.
+    pub fn bind_ipi(vcpu: u32) -> Result<Self, XenError> {
+        let mut op = EvtchnBindIpi {
+            vcpu,
+            ..Default::default()
+        };
+
+        unsafe { call(EVTCHNOP_BIND_IPI, &mut op) }?;
+
+        Ok(Self(EventChannel(op.port)))
+    }
+
+    /// Take ownership of `port`.
+    ///
+    /// # Safety
+    ///
+    /// `port` must not be owned elsewhere, as it is closed on drop.
+    pub unsafe fn from_raw(port: EventChannel) -> Self {
+        Self(port)
+    }
+
+    /// Give up ownership of the port, which stays open.
+    pub fn into_raw(self) -> EventChannel {
+        let port = self.0;
+        mem::forget(self);
+        port
+    }
+
+    /// Close the port, reporting errors unlike drop.
+    pub fn close(self) -> Result<(), XenError> {
+        close(self.into_raw())
+    }
+}
+
+impl Deref for OwnedEventChannel {
+    type Target = EventChannel;
+
+    fn deref(&self) -> &EventChannel {
+        &self.0
+    }
+}
+
+impl Drop for OwnedEventChannel {
+    fn drop(&mut self) {
+        if let Err(e) = close(self.0) {
+            log::warn!("Unable to close event channel {}: {e}", self.0.0);
+        }
+    }
+}
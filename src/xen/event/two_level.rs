@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! 2-level event channel ABI
+//!
+//! Each port has a pending and a mask bit in the shared info page. Xen also
+//! sets, in `evtchn_pending_sel` of the notified vCPU, the bit of the
+//! bitmap word holding the port, so only the selected words need scanning.
+
+use core::sync::atomic::Ordering;
+
+use crate::xen::shared_info::{BITS_PER_XEN_ULONG, SharedInfo, VcpuInfo};
+
+/// Number of ports supported by the 2-level ABI.
+pub const NR_EVENT_CHANNELS: usize = BITS_PER_XEN_ULONG * BITS_PER_XEN_ULONG;
+
+#[inline(always)]
+fn word_bit(port: u32) -> (usize, u64) {
+    let port = port as usize;
+
+    (port / BITS_PER_XEN_ULONG, 1 << (port % BITS_PER_XEN_ULONG))
+}
+
+pub fn is_pending(shared: &SharedInfo, port: u32) -> bool {
+    let (word, bit) = word_bit(port);
+
+    shared.evtchn_pending[word].load(Ordering::Acquire) & bit != 0
+}
+
+pub fn is_masked(shared: &SharedInfo, port: u32) -> bool {
+    let (word, bit) = word_bit(port);
+
+    shared.evtchn_mask[word].load(Ordering::Acquire) & bit != 0
+}
+
+pub fn clear(shared: &SharedInfo, port: u32) {
+    let (word, bit) = word_bit(port);
+
+    shared.evtchn_pending[word].fetch_and(!bit, Ordering::AcqRel);
+}
+
+pub fn mask(shared: &SharedInfo, port: u32) {
+    let (word, bit) = word_bit(port);
+
+    shared.evtchn_mask[word].fetch_or(bit, Ordering::AcqRel);
+}
+
+/// Find the pending and unmasked ports of `vcpu`, clear and give them
+/// to `handle`.
+///
+/// Returns the number of handled events.
+pub fn scan(shared: &SharedInfo, vcpu: &VcpuInfo, mut handle: impl FnMut(u32)) -> usize {
+    let mut handled = 0;
+
+    vcpu.evtchn_upcall_pending.store(0, Ordering::Release);
+
+    loop {
+        let mut selector = vcpu.evtchn_pending_sel.swap(0, Ordering::AcqRel);
+
+        if selector == 0 {
+            break;
+        }
+
+        while selector != 0 {
+            let word = selector.trailing_zeros() as usize;
+            selector &= selector - 1;
+
+            let mut pending = shared.evtchn_pending[word].load(Ordering::Acquire)
+                & !shared.evtchn_mask[word].load(Ordering::Acquire);
+
+            while pending != 0 {
+                let port = (word * BITS_PER_XEN_ULONG) as u32 + pending.trailing_zeros();
+                pending &= pending - 1;
+
+                clear(shared, port);
+                handle(port);
+                handled += 1;
+            }
+        }
+    }
+
+    handled
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn shared_info() -> SharedInfo {
+        // SAFETY: Only made of atomics, for which zero is valid.
+        unsafe { core::mem::zeroed() }
+    }
+
+    /// Flag `port` as Xen does when sending an event.
+    fn raise(shared: &SharedInfo, port: u32) {
+        let (word, bit) = word_bit(port);
+
+        shared.evtchn_pending[word].fetch_or(bit, Ordering::Relaxed);
+        shared.vcpu_info[0]
+            .evtchn_pending_sel
+            .fetch_or(1 << word, Ordering::Relaxed);
+        shared.vcpu_info[0]
+            .evtchn_upcall_pending
+            .store(1, Ordering::Relaxed);
+    }
+
+    fn scan_ports(shared: &SharedInfo) -> Vec<u32> {
+        let mut ports = Vec::new();
+        let handled = scan(shared, &shared.vcpu_info[0], |port| ports.push(port));
+
+        assert_eq!(handled, ports.len());
+        ports
+    }
+
+    #[test]
+    fn dispatch_pending() {
+        let shared = shared_info();
+
+        for port in [3, 1, 64, 130, 4095] {
+            raise(&shared, port);
+        }
+
+        assert_eq!(scan_ports(&shared), [1, 3, 64, 130, 4095]);
+
+        let vcpu = &shared.vcpu_info[0];
+        assert_eq!(vcpu.evtchn_upcall_pending.load(Ordering::Relaxed), 0);
+        assert_eq!(vcpu.evtchn_pending_sel.load(Ordering::Relaxed), 0);
+        assert!(
+            shared
+                .evtchn_pending
+                .iter()
+                .all(|word| word.load(Ordering::Relaxed) == 0)
+        );
+
+        assert!(scan_ports(&shared).is_empty());
+    }
+
+    #[test]
+    fn skip_masked() {
+        let shared = shared_info();
+
+        mask(&shared, 5);
+        raise(&shared, 5);
+        raise(&shared, 6);
+
+        assert_eq!(scan_ports(&shared), [6]);
+
+        // Left pending for when it gets unmasked.
+        assert!(is_pending(&shared, 5));
+        assert!(is_masked(&shared, 5));
+        assert!(!is_pending(&shared, 6));
+        assert_eq!(
+            shared.vcpu_info[0]
+                .evtchn_pending_sel
+                .load(Ordering::Relaxed),
+            0
+        );
+    }
+
+    #[test]
+    fn only_selected_words() {
+        let shared = shared_info();
+
+        // Pending, but its word isn't selected yet.
+        shared.evtchn_pending[2].store(1, Ordering::Relaxed);
+        raise(&shared, 70);
+
+        assert_eq!(scan_ports(&shared), [70]);
+        assert!(is_pending(&shared, 128));
+    }
+}
@@ -0,0 +1,65 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Event channel upcall (x86 HVM/PVH)
+//!
+//! Xen raises [`XEN_UPCALL_VECTOR`] when an event is pending, registered
+//! through HVM_PARAM_CALLBACK_IRQ as a vector callback. Unlike the per-vCPU
+//! HVMOP_set_evtchn_upcall_vector, the interrupt is injected directly and
+//! doesn't go through the local APIC, so it works with the APIC left
+//! disabled and doesn't need an EOI.
+//!
+//! Handlers registered with [`EventChannel::set_handler`] then run in
+//! interrupt context, once interrupts are enabled with
+//! [`interrupts::enable`](crate::arch::x86_64::interrupts::enable).
+//!
+//! [`EventChannel::set_handler`]: super::EventChannel::set_handler
+
+use core::arch::naked_asm;
+
+use crate::{
+    arch::x86_64::idt::{self, CpuRegs},
+    xen::{
+        XenError,
+        hvm::{HvmParam, set_param},
+        shared_info,
+    },
+};
+
+/// IDT vector used for upcalls (same as Linux HYPERVISOR_CALLBACK_VECTOR).
+pub const XEN_UPCALL_VECTOR: u8 = 0xF3;
+
+const HVM_PARAM_CALLBACK_TYPE_VECTOR: u64 = 2;
+const HVM_PARAM_CALLBACK_TYPE_SHIFT: u64 = 56;
+
+extern "C" fn upcall_handler(_regs: &mut CpuRegs, _error_code: u64) {
+    super::dispatch().ok();
+}
+
+#[unsafe(naked)]
+unsafe extern "C" fn raw_upcall_handler() {
+    // No error code is pushed for interrupts, push a dummy one for isr.s.
+    naked_asm!(
+        "pushq $0",
+        include_str!("../../arch/x86_64/isr.s"),
+        options(att_syntax),
+        EXCEPTION_HANDLER = sym upcall_handler
+    )
+}
+
+/// Install the upcall handler and ask Xen to deliver events through it.
+///
+/// Interrupts are left disabled.
+pub fn init() -> Result<(), XenError> {
+    // The handler needs the shared info page, don't map it in interrupt
+    // context.
+    shared_info::map()?;
+
+    unsafe { idt::set_handler(XEN_UPCALL_VECTOR, raw_upcall_handler) };
+
+    set_param(
+        HvmParam::CallbackIrq,
+        (HVM_PARAM_CALLBACK_TYPE_VECTOR << HVM_PARAM_CALLBACK_TYPE_SHIFT)
+            | XEN_UPCALL_VECTOR as u64,
+    )
+}
@@ -0,0 +1,141 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Grant reference allocator
+
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use super::GrantRef;
+use crate::xen::XenError;
+
+/// Grant references reserved for the toolstack (console, XenStore, ...).
+pub const GNTTAB_NR_RESERVED_ENTRIES: u32 = 8;
+
+/// Lock-free bitmap allocator of up to `WORDS * 64` grant references.
+pub struct GrantRefAllocator<const WORDS: usize> {
+    used: [AtomicU64; WORDS],
+}
+
+impl<const WORDS: usize> Default for GrantRefAllocator<WORDS> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<const WORDS: usize> GrantRefAllocator<WORDS> {
+    /// All references but the reserved ones are free.
+    pub const fn new() -> Self {
+        let mut used = [const { AtomicU64::new(0) }; WORDS];
+
+        if WORDS > 0 {
+            used[0] = AtomicU64::new((1 << GNTTAB_NR_RESERVED_ENTRIES) - 1);
+        }
+
+        Self { used }
+    }
+
+    /// Allocate a reference below `limit`, fails with ENOSPC if all are in
+    /// use.
+    pub fn alloc(&self, limit: u32) -> Result<GrantRef, XenError> {
+        for (index, word) in self.used.iter().enumerate() {
+            let base = (index * 64) as u32;
+
+            if base >= limit {
+                break;
+            }
+
+            // Bits past the limit are considered used.
+            let unusable = match limit - base {
+                64.. => 0,
+                valid => !((1u64 << valid) - 1),
+            };
+
+            let mut used = word.load(Ordering::Relaxed);
+
+            while used | unusable != u64::MAX {
+                let bit = (!(used | unusable)).trailing_zeros();
+
+                match word.compare_exchange_weak(
+                    used,
+                    used | (1 << bit),
+                    Ordering::Acquire,
+                    Ordering::Relaxed,
+                ) {
+                    Ok(_) => return Ok(GrantRef(base + bit)),
+                    Err(current) => used = current,
+                }
+            }
+        }
+
+        Err(XenError::NoSpc)
+    }
+
+    /// Release `gref`, which must have been returned by [`Self::alloc`].
+    pub fn free(&self, gref: GrantRef) {
+        if gref.0 < GNTTAB_NR_RESERVED_ENTRIES {
+            return;
+        }
+
+        if let Some(word) = self.used.get(gref.0 as usize / 64) {
+            word.fetch_and(!(1 << (gref.0 % 64)), Ordering::Release);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skip_reserved() {
+        let refs = GrantRefAllocator::<2>::new();
+
+        assert_eq!(refs.alloc(128), Ok(GrantRef(GNTTAB_NR_RESERVED_ENTRIES)));
+
+        // Never handed out, so never released either.
+        refs.free(GrantRef(0));
+        assert_eq!(
+            refs.alloc(128),
+            Ok(GrantRef(GNTTAB_NR_RESERVED_ENTRIES + 1))
+        );
+    }
+
+    #[test]
+    fn recycle() {
+        let refs = GrantRefAllocator::<2>::new();
+        let grefs: Vec<_> = (0..10).map(|_| refs.alloc(128).unwrap()).collect();
+
+        refs.free(grefs[3]);
+        refs.free(grefs[7]);
+
+        assert_eq!(refs.alloc(128), Ok(grefs[3]));
+        assert_eq!(refs.alloc(128), Ok(grefs[7]));
+        assert_eq!(
+            refs.alloc(128),
+            Ok(GrantRef(GNTTAB_NR_RESERVED_ENTRIES + 10))
+        );
+    }
+
+    #[test]
+    fn exhaustion() {
+        let refs = GrantRefAllocator::<2>::new();
+
+        // Across the word boundary, up to the limit.
+        for gref in GNTTAB_NR_RESERVED_ENTRIES..100 {
+            assert_eq!(refs.alloc(100), Ok(GrantRef(gref)));
+        }
+
+        assert_eq!(refs.alloc(100), Err(XenError::NoSpc));
+
+        refs.free(GrantRef(70));
+        assert_eq!(refs.alloc(100), Ok(GrantRef(70)));
+        assert_eq!(refs.alloc(100), Err(XenError::NoSpc));
+
+        // The limit is bounded by the bitmap size.
+        assert_eq!(refs.alloc(1000).map(|gref| gref.0), Ok(100));
+        for _ in 101..128 {
+            refs.alloc(1000).unwrap();
+        }
+        assert_eq!(refs.alloc(1000), Err(XenError::NoSpc));
+    }
+}
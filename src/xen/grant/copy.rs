@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Copy from/to foreign grants (GNTTABOP_copy)
+//!
+//! Xen does the copy, without the grant being mapped. Each segment of a copy
+//! must stay within a single page on both sides, local buffers crossing page
+//! boundaries are thus split in several segments.
+//!
+//! Local buffers are given to Xen by guest frame, found by walking our page
+//! tables. Buffers that aren't mapped are rejected.
+
+use core::{marker::PhantomData, ops::Range, ptr};
+
+use crate::{
+    arch::virt_to_pfn,
+    xen::{DomId, XenError},
+};
+
+use super::{GrantRef, grant_table_op, status_result};
+
+const GNTTABOP_COPY: usize = 5;
+
+const PAGE_SIZE: usize = 4096;
+
+bitflags::bitflags! {
+    /// GNTCOPY_* flags
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct GrantCopyFlags: u16 {
+        /// The source is a grant reference rather than a local frame.
+        const SOURCE_GREF = 1 << 0;
+        /// The destination is a grant reference rather than a local frame.
+        const DEST_GREF = 1 << 1;
+    }
+}
+
+/// Source or destination of a gnttab_copy, `u` is either a grant reference
+/// or a guest frame.
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GnttabCopyPtr {
+    u: u64,
+    domid: u16,
+    offset: u16,
+}
+
+/// struct gnttab_copy
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GnttabCopy {
+    source: GnttabCopyPtr,
+    dest: GnttabCopyPtr,
+    len: u16,
+    flags: u16,
+    status: i16,
+}
+
+const _: () = assert!(size_of::<GnttabCopy>() == 40);
+
+/// Side of a copy segment
+#[derive(Clone, Copy)]
+enum Side {
+    Grant(DomId, GrantRef),
+    Local,
+}
+
+/// Batch of up to `N` copy segments.
+///
+/// Local buffers stay borrowed until the batch is submitted.
+pub struct GrantCopy<'a, const N: usize> {
+    segments: [GnttabCopy; N],
+    len: usize,
+    _buffers: PhantomData<&'a mut [u8]>,
+}
+
+impl<const N: usize> Default for GrantCopy<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> GrantCopy<'a, N> {
+    pub const fn new() -> Self {
+        Self {
+            segments: [GnttabCopy {
+                source: GnttabCopyPtr {
+                    u: 0,
+                    domid: 0,
+                    offset: 0,
+                },
+                dest: GnttabCopyPtr {
+                    u: 0,
+                    domid: 0,
+                    offset: 0,
+                },
+                len: 0,
+                flags: 0,
+                status: 0,
+            }; N],
+            len: 0,
+            _buffers: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queue a copy from `offset` in grant `gref` of `domid` into `dest`.
+    ///
+    /// Returns the indexes of the queued segments.
+    pub fn from_grant(
+        &mut self,
+        domid: DomId,
+        gref: GrantRef,
+        offset: usize,
+        dest: &'a mut [u8],
+    ) -> Result<Range<usize>, XenError> {
+        self.push(
+            Side::Grant(domid, gref),
+            Side::Local,
+            offset,
+            ptr::from_mut(dest).addr(),
+            dest.len(),
+        )
+    }
+
+    /// Queue a copy of `source` to `offset` in grant `gref` of `domid`.
+    ///
+    /// Returns the indexes of the queued segments.
+    pub fn to_grant(
+        &mut self,
+        source: &'a [u8],
+        domid: DomId,
+        gref: GrantRef,
+        offset: usize,
+    ) -> Result<Range<usize>, XenError> {
+        self.push(
+            Side::Local,
+            Side::Grant(domid, gref),
+            offset,
+            ptr::from_ref(source).addr(),
+            source.len(),
+        )
+    }
+
+    /// Queue the segments of a copy between a grant (at `offset`) and a
+    /// local buffer (at `addr`), split at local page boundaries.
+    fn push(
+        &mut self,
+        source: Side,
+        dest: Side,
+        offset: usize,
+        addr: usize,
+        len: usize,
+    ) -> Result<Range<usize>, XenError> {
+        // A grant covers a single page.
+        if offset.checked_add(len).is_none_or(|end| end > PAGE_SIZE) {
+            return Err(XenError::Inval);
+        }
+
+        let nr_segments = (addr + len).div_ceil(PAGE_SIZE) - addr / PAGE_SIZE;
+        if self.len + nr_segments > N {
+            return Err(XenError::NoSpc);
+        }
+
+        let start = self.len;
+        let mut done = 0;
+
+        while done < len {
+            let local = addr + done;
+            let seg_len = (len - done).min(PAGE_SIZE - local % PAGE_SIZE);
+
+            let Some(local_pfn) = virt_to_pfn(local) else {
+                // Drop the segments queued for this buffer.
+                self.len = start;
+                return Err(XenError::Inval);
+            };
+
+            let local_ptr = GnttabCopyPtr {
+                u: local_pfn,
+                domid: DomId::SELF.0,
+                offset: (local % PAGE_SIZE) as u16,
+            };
+            let grant_ptr = |domid: DomId, gref: GrantRef| GnttabCopyPtr {
+                u: gref.0 as u64,
+                domid: domid.0,
+                offset: (offset + done) as u16,
+            };
+
+            let mut flags = GrantCopyFlags::empty();
+            let segment = &mut self.segments[self.len];
+
+            segment.source = match source {
+                Side::Grant(domid, gref) => {
+                    flags |= GrantCopyFlags::SOURCE_GREF;
+                    grant_ptr(domid, gref)
+                }
+                Side::Local => local_ptr,
+            };
+            segment.dest = match dest {
+                Side::Grant(domid, gref) => {
+                    flags |= GrantCopyFlags::DEST_GREF;
+                    grant_ptr(domid, gref)
+                }
+                Side::Local => local_ptr,
+            };
+            segment.len = seg_len as u16;
+            segment.flags = flags.bits();
+            segment.status = 0;
+
+            self.len += 1;
+            done += seg_len;
+        }
+
+        Ok(start..self.len)
+    }
+
+    /// Issue all the queued segments.
+    ///
+    /// The error of the hypercall itself is returned, per segment results are
+    /// available through [`GrantCopyResults`].
+    pub fn submit(mut self) -> Result<GrantCopyResults<N>, XenError> {
+        if self.len > 0 {
+            unsafe { grant_table_op(GNTTABOP_COPY, self.segments.as_mut_ptr(), self.len) }?;
+        }
+
+        Ok(GrantCopyResults {
+            status: self.segments.map(|segment| segment.status),
+            len: self.len,
+        })
+    }
+}
+
+/// Per segment results of a submitted [`GrantCopy`].
+pub struct GrantCopyResults<const N: usize> {
+    status: [i16; N],
+    len: usize,
+}
+
+impl<const N: usize> GrantCopyResults<N> {
+    /// Result of segment `index`, `None` if out of bounds.
+    pub fn get(&self, index: usize) -> Option<Result<(), XenError>> {
+        (index < self.len).then(|| status_result(self.status[index]))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<(), XenError>> + '_ {
+        self.status[..self.len]
+            .iter()
+            .map(|&status| status_result(status))
+    }
+
+    /// First failing segment, as (index, error).
+    pub fn first_error(&self) -> Option<(usize, XenError)> {
+        self.iter()
+            .enumerate()
+            .find_map(|(i, result)| result.err().map(|e| (i, e)))
+    }
+
+    /// Overall result of the segments in `range` (as returned when queued).
+    pub fn range(&self, mut range: Range<usize>) -> Result<(), XenError> {
+        range.try_for_each(|index| self.get(index).unwrap_or(Err(XenError::Inval)))
+    }
+}
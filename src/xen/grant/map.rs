@@ -0,0 +1,404 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Mapping of foreign grants (GNTTABOP_map_grant_ref)
+//!
+//! As an auto-translated guest, `host_addr` is a guest physical address:
+//! Xen puts the granted frame in our physmap there. Grants are thus mapped
+//! in a pool of static slots, whose original frames are given back to Xen
+//! on first use. Each slot then gets an unencrypted alias through
+//! [`map_4k_frame`] (the `mm` L1 window on x86, the identity mapping of the
+//! translation tables on Arm and RISC-V) that is kept for later mappings.
+//!
+//! The pool bounds the number of grants mapped at once to
+//! [`GNTTAB_MAX_MAPPINGS`], which can be set at build time with the
+//! `XRTF_GNTTAB_MAX_MAPPINGS` environment variable.
+
+use core::{
+    array,
+    cell::SyncUnsafeCell,
+    mem::ManuallyDrop,
+    ptr::{self, NonNull},
+    sync::atomic::{AtomicPtr, AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::{
+    arch::map_4k_frame,
+    xen::{DomId, XenError, memory::decrease_reservation},
+};
+
+use super::{GrantRef, grant_table_op, status_result};
+
+const GNTTABOP_MAP_GRANT_REF: usize = 0;
+const GNTTABOP_UNMAP_GRANT_REF: usize = 1;
+
+/// Number of grants that can be mapped at once (`XRTF_GNTTAB_MAX_MAPPINGS`,
+/// 16 by default).
+///
+/// Each one takes a page of the image, whose frame is given back to Xen. On
+/// x86, this is bounded by the L1 window entries left by the other mappings
+/// (see [`L1_WINDOW_OTHER_FRAMES`]), and the image must still fit below
+/// 2 MiB with its stack, which the linker script checks.
+pub const GNTTAB_MAX_MAPPINGS: usize = match option_env!("XRTF_GNTTAB_MAX_MAPPINGS") {
+    Some(value) => parse_usize(value),
+    None => 16,
+};
+
+const _: () = assert!(GNTTAB_MAX_MAPPINGS > 0);
+
+/// L1 window entries used by other mappings on x86: the GHCB, shared info,
+/// console and xenstore pages, the FIFO control block and event array, and
+/// the grant table frames.
+#[cfg(target_arch = "x86_64")]
+pub const L1_WINDOW_OTHER_FRAMES: usize =
+    4 + 1 + crate::xen::event::fifo::EVENT_ARRAY_PAGES + super::GNTTAB_MAX_GPFNS;
+
+#[cfg(target_arch = "x86_64")]
+const _: () = assert!(
+    GNTTAB_MAX_MAPPINGS <= crate::arch::x86_64::mm::L1_WINDOW_FRAMES - L1_WINDOW_OTHER_FRAMES,
+    "XRTF_GNTTAB_MAX_MAPPINGS doesn't fit in the L1 window"
+);
+
+/// Number of words of the slot bitmaps.
+const SLOT_WORDS: usize = GNTTAB_MAX_MAPPINGS.div_ceil(64);
+
+const fn parse_usize(value: &str) -> usize {
+    let bytes = value.as_bytes();
+    let mut result = 0;
+    let mut i = 0;
+
+    assert!(!bytes.is_empty(), "XRTF_GNTTAB_MAX_MAPPINGS is empty");
+
+    while i < bytes.len() {
+        assert!(
+            bytes[i].is_ascii_digit(),
+            "XRTF_GNTTAB_MAX_MAPPINGS is not a number"
+        );
+
+        result = result * 10 + (bytes[i] - b'0') as usize;
+        i += 1;
+    }
+
+    result
+}
+
+bitflags::bitflags! {
+    /// GNTMAP_* flags
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct GrantMapFlags: u32 {
+        const DEVICE_MAP = 1 << 0;
+        const HOST_MAP = 1 << 1;
+        const READONLY = 1 << 2;
+        const APPLICATION_MAP = 1 << 3;
+        const CONTAINS_PTE = 1 << 4;
+    }
+}
+
+/// struct gnttab_map_grant_ref
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GnttabMapGrantRef {
+    host_addr: u64,
+    flags: u32,
+    gref: u32,
+    dom: u16,
+    status: i16,
+    handle: u32,
+    dev_bus_addr: u64,
+}
+
+/// struct gnttab_unmap_grant_ref
+#[repr(C)]
+#[derive(Clone, Copy, Default)]
+struct GnttabUnmapGrantRef {
+    host_addr: u64,
+    dev_bus_addr: u64,
+    handle: u32,
+    status: i16,
+}
+
+#[repr(C, align(4096))]
+struct MapSlot([u8; 4096]);
+
+/// Guest frames where grants get mapped.
+static MAP_SLOTS: [SyncUnsafeCell<MapSlot>; GNTTAB_MAX_MAPPINGS] =
+    [const { SyncUnsafeCell::new(MapSlot([0; _])) }; _];
+
+/// Slots in use, one bit per slot.
+static SLOTS_USED: [AtomicU64; SLOT_WORDS] = [const { AtomicU64::new(0) }; _];
+
+/// Slots that failed to unmap, still reserved until [`reclaim_slots`]
+/// manages to unmap them.
+static SLOTS_STALE: [AtomicU64; SLOT_WORDS] = [const { AtomicU64::new(0) }; _];
+
+/// Grant handle of each stale slot.
+static SLOT_HANDLE: [AtomicU32; GNTTAB_MAX_MAPPINGS] = [const { AtomicU32::new(0) }; _];
+
+/// Alias of each slot, null until the slot is first used.
+static SLOT_ALIAS: [AtomicPtr<u8>; GNTTAB_MAX_MAPPINGS] =
+    [const { AtomicPtr::new(ptr::null_mut()) }; _];
+
+fn slot_gpfn(slot: usize) -> u64 {
+    (MAP_SLOTS[slot].get().addr() >> 12) as u64
+}
+
+fn free_slot(slot: usize) {
+    SLOTS_USED[slot / 64].fetch_and(!(1 << (slot % 64)), Ordering::Release);
+}
+
+/// Keep `slot` reserved after failing to unmap `handle` from it, to retry
+/// later.
+fn mark_stale(slot: usize, handle: u32) {
+    SLOT_HANDLE[slot].store(handle, Ordering::Relaxed);
+    SLOTS_STALE[slot / 64].fetch_or(1 << (slot % 64), Ordering::Release);
+}
+
+/// Retry unmapping the stale slots, returns the number of slots freed.
+pub fn reclaim_slots() -> usize {
+    let mut freed = 0;
+
+    for (index, word) in SLOTS_STALE.iter().enumerate() {
+        // Take all of them, the ones still failing are put back.
+        let mut stale = word.swap(0, Ordering::Acquire);
+
+        while stale != 0 {
+            let slot = index * 64 + stale.trailing_zeros() as usize;
+            let handle = SLOT_HANDLE[slot].load(Ordering::Relaxed);
+            stale &= stale - 1;
+
+            let mut op = GnttabUnmapGrantRef {
+                host_addr: slot_gpfn(slot) << 12,
+                handle,
+                ..Default::default()
+            };
+
+            let ret = unsafe { grant_table_op(GNTTABOP_UNMAP_GRANT_REF, &raw mut op, 1) };
+
+            match ret.and_then(|_| status_result(op.status)) {
+                Ok(()) => {
+                    free_slot(slot);
+                    freed += 1;
+                }
+                Err(_) => mark_stale(slot, handle),
+            }
+        }
+    }
+
+    freed
+}
+
+/// Reserve a free slot, if any.
+fn reserve_slot() -> Option<usize> {
+    for (index, word) in SLOTS_USED.iter().enumerate() {
+        // Bits past the last slot are considered used.
+        let unusable = match GNTTAB_MAX_MAPPINGS - index * 64 {
+            64.. => 0,
+            valid => !((1u64 << valid) - 1),
+        };
+
+        let mut used = word.load(Ordering::Relaxed);
+
+        while used | unusable != u64::MAX {
+            let bit = (!(used | unusable)).trailing_zeros();
+
+            match word.compare_exchange_weak(
+                used,
+                used | (1 << bit),
+                Ordering::Acquire,
+                Ordering::Relaxed,
+            ) {
+                Ok(_) => return Some(index * 64 + bit as usize),
+                Err(current) => used = current,
+            }
+        }
+    }
+
+    None
+}
+
+/// Reserve a slot, releasing its original frame on first use.
+///
+/// Stale slots are reclaimed once all the others are in use.
+fn alloc_slot() -> Result<usize, XenError> {
+    let slot = match reserve_slot() {
+        Some(slot) => slot,
+        None if reclaim_slots() != 0 => reserve_slot().ok_or(XenError::NoSpc)?,
+        None => return Err(XenError::NoSpc),
+    };
+
+    if SLOT_ALIAS[slot].load(Ordering::Acquire).is_null() {
+        let gpfn = slot_gpfn(slot);
+
+        // SAFETY: The slot is only used for grant mappings.
+        let released = unsafe { decrease_reservation(&[gpfn]) };
+
+        // The mapped frame is owned by the remote domain, access it as shared
+        // memory.
+        let alias = match released {
+            Ok(1) => map_4k_frame::<u8>(gpfn, false).ok_or(XenError::NoMem),
+            Ok(_) => Err(XenError::Busy),
+            Err(e) => Err(e),
+        };
+
+        match alias {
+            Ok(alias) => SLOT_ALIAS[slot].store(alias.as_ptr(), Ordering::Release),
+            Err(e) => {
+                free_slot(slot);
+                return Err(e);
+            }
+        }
+    }
+
+    Ok(slot)
+}
+
+/// A foreign grant mapped in our address space, unmapped on drop.
+pub struct GrantMapping {
+    slot: usize,
+    handle: u32,
+    readonly: bool,
+}
+
+impl GrantMapping {
+    /// Map grant `gref` of `domid`.
+    pub fn map(domid: DomId, gref: GrantRef, readonly: bool) -> Result<Self, XenError> {
+        let [mapping] = map_batch(domid, [gref], readonly);
+
+        mapping
+    }
+
+    /// Start of the mapped page.
+    pub fn as_ptr(&self) -> NonNull<u8> {
+        // SAFETY: The alias is set before the slot is handed out.
+        unsafe { NonNull::new_unchecked(SLOT_ALIAS[self.slot].load(Ordering::Acquire)) }
+    }
+
+    /// Guest frame where the grant is mapped.
+    pub fn gpfn(&self) -> u64 {
+        slot_gpfn(self.slot)
+    }
+
+    /// Handle given by Xen (grant_handle_t).
+    pub fn handle(&self) -> u32 {
+        self.handle
+    }
+
+    pub fn is_readonly(&self) -> bool {
+        self.readonly
+    }
+
+    /// Unmap the grant.
+    ///
+    /// On failure, the slot stays reserved as the frame may still be mapped,
+    /// unmapping it is retried by [`reclaim_slots`].
+    pub fn unmap(self) -> Result<(), XenError> {
+        let [result] = unmap_batch([self]);
+
+        result
+    }
+}
+
+impl Drop for GrantMapping {
+    fn drop(&mut self) {
+        let mapping = GrantMapping {
+            slot: self.slot,
+            handle: self.handle,
+            readonly: self.readonly,
+        };
+
+        let [result] = unmap_batch([mapping]);
+
+        if let Err(e) = result {
+            log::warn!(
+                "Unable to unmap grant handle {}, will retry: {e}",
+                self.handle
+            );
+        }
+    }
+}
+
+/// Map the grants `grefs` of `domid` with a single hypercall.
+pub fn map_batch<const N: usize>(
+    domid: DomId,
+    grefs: [GrantRef; N],
+    readonly: bool,
+) -> [Result<GrantMapping, XenError>; N] {
+    let mut results = array::from_fn(|_| Err(XenError::NoSpc));
+    let mut ops = [GnttabMapGrantRef::default(); N];
+    let mut slots = [(0, 0); N];
+    let mut count = 0;
+
+    let mut flags = GrantMapFlags::HOST_MAP;
+    if readonly {
+        flags |= GrantMapFlags::READONLY;
+    }
+
+    for (index, gref) in grefs.into_iter().enumerate() {
+        let slot = match alloc_slot() {
+            Ok(slot) => slot,
+            Err(e) => {
+                results[index] = Err(e);
+                continue;
+            }
+        };
+
+        ops[count] = GnttabMapGrantRef {
+            host_addr: slot_gpfn(slot) << 12,
+            flags: flags.bits(),
+            gref: gref.0,
+            dom: domid.0,
+            ..Default::default()
+        };
+        slots[count] = (index, slot);
+        count += 1;
+    }
+
+    if count == 0 {
+        return results;
+    }
+
+    let ret = unsafe { grant_table_op(GNTTABOP_MAP_GRANT_REF, ops.as_mut_ptr(), count) };
+
+    for (op, &(index, slot)) in ops.iter().zip(&slots).take(count) {
+        results[index] = match ret.and_then(|_| status_result(op.status)) {
+            Ok(()) => Ok(GrantMapping {
+                slot,
+                handle: op.handle,
+                readonly,
+            }),
+            Err(e) => {
+                free_slot(slot);
+                Err(e)
+            }
+        };
+    }
+
+    results
+}
+
+/// Unmap `mappings` with a single hypercall.
+///
+/// Slots of mappings that fail to unmap stay reserved, unmapping them is
+/// retried by [`reclaim_slots`].
+pub fn unmap_batch<const N: usize>(mappings: [GrantMapping; N]) -> [Result<(), XenError>; N] {
+    let mappings = mappings.map(ManuallyDrop::new);
+    let mut ops = mappings.each_ref().map(|mapping| GnttabUnmapGrantRef {
+        host_addr: mapping.gpfn() << 12,
+        handle: mapping.handle,
+        ..Default::default()
+    });
+
+    if N == 0 {
+        return array::from_fn(|_| Ok(()));
+    }
+
+    let ret = unsafe { grant_table_op(GNTTABOP_UNMAP_GRANT_REF, ops.as_mut_ptr(), N) };
+
+    array::from_fn(|index| {
+        let mapping = &mappings[index];
+
+        ret.and_then(|_| status_result(ops[index].status))
+            .inspect(|()| free_slot(mapping.slot))
+            .inspect_err(|_| mark_stale(mapping.slot, mapping.handle))
+    })
+}
@@ -0,0 +1,501 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Grant tables (`__HYPERVISOR_grant_table_op`)
+//!
+//! Grants let another domain (usually a backend) access some of our frames.
+//! The grant table frames are mapped into our physmap, at static pages on
+//! x86 and in the region advertised by the device tree on Arm and RISC-V.
+//!
+//! Version 2 (with sub-page and transitive grants) is used when Xen allows
+//! it, version 1 otherwise.
+
+use core::{ptr::NonNull, sync::atomic::AtomicU16};
+
+use atomic_refcell::{AtomicRef, AtomicRefCell};
+
+use crate::{
+    arch::map_4k_frame,
+    xen::{
+        DomId, XenError,
+        hypercall::hypercall3,
+        memory::{XENMAPIDX_GRANT_TABLE_STATUS, XenMapSpace, add_to_physmap},
+    },
+};
+
+pub mod alloc;
+pub mod copy;
+pub mod map;
+pub mod v1;
+pub mod v2;
+
+use alloc::GrantRefAllocator;
+use v1::{GRANT_ENTRIES_PER_FRAME, GrantEntryV1, GrantFrameV1};
+use v2::{
+    GRANT_ENTRIES_PER_FRAME_V2, GRANT_STATUS_PER_FRAME, GrantEntryV2, GrantFrameV2,
+    GrantStatusFrame,
+};
+
+pub(crate) const GRANT_TABLE_OP: usize = 20;
+
+const GNTTABOP_QUERY_SIZE: usize = 6;
+const GNTTABOP_SET_VERSION: usize = 8;
+const GNTTABOP_GET_STATUS_FRAMES: usize = 9;
+const GNTTABOP_GET_VERSION: usize = 10;
+
+/// Highest number of grant table frames we map.
+pub const GNTTAB_MAX_FRAMES: usize = 4;
+
+/// Highest number of grant references we may use (with v1).
+pub const GNTTAB_MAX_ENTRIES: usize = GNTTAB_MAX_FRAMES * GRANT_ENTRIES_PER_FRAME;
+
+/// Highest number of v2 status frames we map.
+pub const GNTTAB_MAX_STATUS_FRAMES: usize =
+    (GNTTAB_MAX_FRAMES * GRANT_ENTRIES_PER_FRAME_V2).div_ceil(GRANT_STATUS_PER_FRAME);
+
+/// Bits of the entry flags holding its [`GrantType`] (GTF_type_mask).
+pub const GTF_TYPE_MASK: u16 = 3;
+
+/// Type of a grant entry (GTF_invalid, GTF_permit_access, ...)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u16)]
+pub enum GrantType {
+    Invalid = 0,
+    PermitAccess = 1,
+    AcceptTransfer = 2,
+    /// Grant of another grant (v2 only).
+    Transitive = 3,
+}
+
+impl GrantType {
+    /// Type of an entry from its flags.
+    pub fn from_flags(flags: u16) -> Self {
+        match flags & GTF_TYPE_MASK {
+            0 => Self::Invalid,
+            1 => Self::PermitAccess,
+            2 => Self::AcceptTransfer,
+            _ => Self::Transitive,
+        }
+    }
+
+    /// Entry flags of this type along with `flags`.
+    pub fn with(self, flags: GrantFlags) -> u16 {
+        self as u16 | flags.bits()
+    }
+}
+
+bitflags::bitflags! {
+    /// GTF_* flags of a grant entry, besides its [`GrantType`]
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub struct GrantFlags: u16 {
+        const READONLY = 1 << 2;
+        /// Set by Xen while the frame is mapped or copied from.
+        const READING = 1 << 3;
+        /// Set by Xen while the frame is mapped writable or copied to.
+        const WRITING = 1 << 4;
+        const PWT = 1 << 5;
+        const PCD = 1 << 6;
+        const PAT = 1 << 7;
+        /// Only part of the frame is granted (v2 only).
+        const SUB_PAGE = 1 << 8;
+    }
+}
+
+const _: () = assert!(GrantFlags::all().bits() & GTF_TYPE_MASK == 0);
+
+/// Grant reference (grant_ref_t)
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct GrantRef(pub u32);
+
+/// Turn a GNTST_* status into a result.
+pub fn status_result(status: i16) -> Result<(), XenError> {
+    match status {
+        0 => Ok(()),
+        // GNTST_bad_domain
+        -2 => Err(XenError::Srch),
+        // GNTST_bad_gntref, GNTST_bad_page, GNTST_bad_copy_arg
+        -3 | -9 | -10 => Err(XenError::Inval),
+        // GNTST_bad_handle
+        -4 => Err(XenError::BadF),
+        // GNTST_bad_virt_addr, GNTST_bad_dev_addr
+        -5 | -6 => Err(XenError::Fault),
+        // GNTST_no_device_space, GNTST_no_space
+        -7 | -13 => Err(XenError::NoSpc),
+        // GNTST_permission_denied
+        -8 => Err(XenError::Perm),
+        // GNTST_address_too_big
+        -11 => Err(XenError::TooBig),
+        // GNTST_eagain
+        -12 => Err(XenError::Again),
+        // GNTST_general_error and unknown ones
+        _ => Err(XenError::Io),
+    }
+}
+
+/// Issue `count` grant table operations of type `cmd`.
+///
+/// # Safety
+///
+/// `ops` must point to `count` operations of the type expected by `cmd`.
+pub(crate) unsafe fn grant_table_op<T>(
+    cmd: usize,
+    ops: *mut T,
+    count: usize,
+) -> Result<usize, XenError> {
+    unsafe { hypercall3(GRANT_TABLE_OP, [cmd, ops.addr(), count]) }
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GnttabQuerySize {
+    dom: u16,
+    nr_frames: u32,
+    max_nr_frames: u32,
+    status: i16,
+}
+
+/// Current and maximum number of grant table frames of `domid`.
+pub fn query_size(domid: DomId) -> Result<(u32, u32), XenError> {
+    let mut op = GnttabQuerySize {
+        dom: domid.0,
+        ..Default::default()
+    };
+
+    unsafe { grant_table_op(GNTTABOP_QUERY_SIZE, &raw mut op, 1) }?;
+    status_result(op.status)?;
+
+    Ok((op.nr_frames, op.max_nr_frames))
+}
+
+/// Grant table layout version
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum GrantTableVersion {
+    V1 = 1,
+    V2 = 2,
+}
+
+#[repr(C)]
+struct GnttabSetVersion {
+    version: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GnttabGetVersion {
+    dom: u16,
+    _pad: u16,
+    version: u32,
+}
+
+#[repr(C)]
+#[derive(Default)]
+struct GnttabGetStatusFrames {
+    nr_frames: u32,
+    dom: u16,
+    status: i16,
+    frame_list: u64,
+}
+
+/// Switch the grant table of the current domain to `version`.
+///
+/// Only possible while no grant is in use, the reserved entries are
+/// converted by Xen.
+pub fn set_version(version: GrantTableVersion) -> Result<(), XenError> {
+    let mut op = GnttabSetVersion {
+        version: version as u32,
+    };
+
+    unsafe { grant_table_op(GNTTABOP_SET_VERSION, &raw mut op, 1) }?;
+
+    Ok(())
+}
+
+/// Grant table version of `domid`.
+pub fn get_version(domid: DomId) -> Result<GrantTableVersion, XenError> {
+    let mut op = GnttabGetVersion {
+        dom: domid.0,
+        ..Default::default()
+    };
+
+    unsafe { grant_table_op(GNTTABOP_GET_VERSION, &raw mut op, 1) }?;
+
+    match op.version {
+        1 => Ok(GrantTableVersion::V1),
+        2 => Ok(GrantTableVersion::V2),
+        _ => Err(XenError::Inval),
+    }
+}
+
+/// Fill `frames` with the frames of the v2 status frames of `domid`.
+///
+/// Auto-translated guests map them with [`add_to_physmap`] and
+/// [`XENMAPIDX_GRANT_TABLE_STATUS`] instead, this reports where they are.
+pub fn get_status_frames(domid: DomId, frames: &mut [u64]) -> Result<(), XenError> {
+    let mut op = GnttabGetStatusFrames {
+        nr_frames: frames.len() as u32,
+        dom: domid.0,
+        frame_list: frames.as_mut_ptr().addr() as u64,
+        ..Default::default()
+    };
+
+    unsafe { grant_table_op(GNTTABOP_GET_STATUS_FRAMES, &raw mut op, 1) }?;
+    status_result(op.status)
+}
+
+/// Number of guest frames the grant table may use.
+const GNTTAB_MAX_GPFNS: usize = GNTTAB_MAX_FRAMES + GNTTAB_MAX_STATUS_FRAMES;
+
+#[cfg(target_arch = "x86_64")]
+#[repr(C, align(4096))]
+struct GrantPage([u8; 4096]);
+
+/// Guest frames replaced by the grant table frames.
+#[cfg(target_arch = "x86_64")]
+static GRANT_PAGES: [core::cell::SyncUnsafeCell<GrantPage>; GNTTAB_MAX_GPFNS] =
+    [const { core::cell::SyncUnsafeCell::new(GrantPage([0; _])) }; _];
+
+/// Guest frames available for the grant table.
+#[cfg(target_arch = "x86_64")]
+fn grant_gpfns() -> Result<impl Iterator<Item = u64>, XenError> {
+    Ok(GRANT_PAGES
+        .iter()
+        .map(|page| (page.get().addr() >> 12) as u64))
+}
+
+/// Guest frames available for the grant table.
+#[cfg(not(target_arch = "x86_64"))]
+fn grant_gpfns() -> Result<impl Iterator<Item = u64>, XenError> {
+    let (base, size) = crate::xen::detect()
+        .and_then(|info| info.grant_table)
+        .ok_or(XenError::NoDev)?;
+
+    Ok((base >> 12..(base + size as u64) >> 12).take(GNTTAB_MAX_GPFNS))
+}
+
+/// Grant entry of either version, with its status for v2.
+enum GrantEntry<'a> {
+    V1(&'a GrantEntryV1),
+    V2(&'a GrantEntryV2, &'a AtomicU16),
+}
+
+struct GrantTable {
+    version: GrantTableVersion,
+    frames: [Option<NonNull<u8>>; GNTTAB_MAX_FRAMES],
+    nr_frames: usize,
+    status: [Option<NonNull<GrantStatusFrame>>; GNTTAB_MAX_STATUS_FRAMES],
+}
+
+unsafe impl Send for GrantTable {}
+unsafe impl Sync for GrantTable {}
+
+impl GrantTable {
+    fn entries_per_frame(&self) -> usize {
+        match self.version {
+            GrantTableVersion::V1 => GRANT_ENTRIES_PER_FRAME,
+            GrantTableVersion::V2 => GRANT_ENTRIES_PER_FRAME_V2,
+        }
+    }
+
+    fn nr_entries(&self) -> usize {
+        self.nr_frames * self.entries_per_frame()
+    }
+
+    fn entry(&self, gref: GrantRef) -> Option<GrantEntry<'_>> {
+        let gref = gref.0 as usize;
+        let per_frame = self.entries_per_frame();
+        let frame = self.frames.get(gref / per_frame)?.as_ref()?;
+
+        // SAFETY: Grant table frames stay mapped once set up, and are laid
+        // out according to the version.
+        Some(match self.version {
+            GrantTableVersion::V1 => {
+                GrantEntry::V1(unsafe { &frame.cast::<GrantFrameV1>().as_ref()[gref % per_frame] })
+            }
+            GrantTableVersion::V2 => {
+                let status = self.status.get(gref / GRANT_STATUS_PER_FRAME)?.as_ref()?;
+
+                unsafe {
+                    GrantEntry::V2(
+                        &frame.cast::<GrantFrameV2>().as_ref()[gref % per_frame],
+                        &status.as_ref()[gref % GRANT_STATUS_PER_FRAME],
+                    )
+                }
+            }
+        })
+    }
+}
+
+static GRANT_TABLE: AtomicRefCell<Option<GrantTable>> = AtomicRefCell::new(None);
+static GRANT_REFS: GrantRefAllocator<{ GNTTAB_MAX_ENTRIES / 64 }> = GrantRefAllocator::new();
+
+/// Map the frames of a grant table of `version`.
+fn setup(version: GrantTableVersion) -> Result<GrantTable, XenError> {
+    let (_, max_nr_frames) = query_size(DomId::SELF)?;
+
+    let mut table = GrantTable {
+        version,
+        frames: [None; _],
+        nr_frames: 0,
+        status: [None; _],
+    };
+
+    let mut gpfns = grant_gpfns()?;
+
+    for (idx, gpfn) in gpfns
+        .by_ref()
+        .take(GNTTAB_MAX_FRAMES.min(max_nr_frames as usize))
+        .enumerate()
+    {
+        // SAFETY: The frames returned by grant_gpfns are reserved for the
+        // grant table.
+        unsafe { add_to_physmap(XenMapSpace::GrantTable, idx as u64, gpfn) }?;
+
+        // Xen doesn't know about memory encryption, access it as shared memory.
+        table.frames[idx] = Some(map_4k_frame(gpfn, false).ok_or(XenError::NoMem)?);
+        table.nr_frames = idx + 1;
+    }
+
+    if table.nr_frames == 0 {
+        return Err(XenError::NoSpc);
+    }
+
+    if version == GrantTableVersion::V2 {
+        let nr_status =
+            (table.nr_frames * GRANT_ENTRIES_PER_FRAME_V2).div_ceil(GRANT_STATUS_PER_FRAME);
+
+        for idx in 0..nr_status {
+            let gpfn = gpfns.next().ok_or(XenError::NoSpc)?;
+            let idx_status = idx as u64 | XENMAPIDX_GRANT_TABLE_STATUS;
+
+            // SAFETY: See above.
+            unsafe { add_to_physmap(XenMapSpace::GrantTable, idx_status, gpfn) }?;
+
+            table.status[idx] = Some(map_4k_frame(gpfn, false).ok_or(XenError::NoMem)?);
+        }
+    }
+
+    Ok(table)
+}
+
+/// Map the grant table frames, does nothing if already done.
+///
+/// Version 2 is tried first, falling back to version 1 if Xen refuses it.
+pub fn init() -> Result<(), XenError> {
+    if GRANT_TABLE.borrow().is_some() {
+        return Ok(());
+    }
+
+    let table = match set_version(GrantTableVersion::V2).and_then(|()| setup(GrantTableVersion::V2))
+    {
+        Ok(table) => table,
+        Err(e) => {
+            log::info!("Grant table v2 unavailable ({e}), using v1");
+
+            // Xen may have switched to v2 before we failed.
+            if get_version(DomId::SELF) != Ok(GrantTableVersion::V1) {
+                set_version(GrantTableVersion::V1)?;
+            }
+
+            setup(GrantTableVersion::V1)?
+        }
+    };
+
+    *GRANT_TABLE.borrow_mut() = Some(table);
+
+    Ok(())
+}
+
+fn table() -> Result<AtomicRef<'static, GrantTable>, XenError> {
+    init()?;
+
+    AtomicRef::filter_map(GRANT_TABLE.borrow(), Option::as_ref).ok_or(XenError::NoDev)
+}
+
+/// Version of the grant table in use.
+pub fn version() -> Result<GrantTableVersion, XenError> {
+    Ok(table()?.version)
+}
+
+/// Number of grant references available, reserved ones included.
+pub fn nr_entries() -> Result<usize, XenError> {
+    Ok(table()?.nr_entries())
+}
+
+/// Allocate a grant reference and fill its entry with `grant`.
+fn grant_with(
+    grant: impl FnOnce(GrantEntry) -> Result<(), XenError>,
+) -> Result<GrantRef, XenError> {
+    let table = table()?;
+    let gref = GRANT_REFS.alloc(table.nr_entries() as u32)?;
+
+    if let Err(e) = table.entry(gref).ok_or(XenError::Inval).and_then(grant) {
+        GRANT_REFS.free(gref);
+        return Err(e);
+    }
+
+    Ok(gref)
+}
+
+/// Let `domid` access our frame `frame`, read-only if `readonly`.
+pub fn grant_access(domid: DomId, frame: u64, readonly: bool) -> Result<GrantRef, XenError> {
+    grant_with(|entry| match entry {
+        GrantEntry::V1(entry) => entry.grant_access(domid, frame, readonly),
+        GrantEntry::V2(entry, _) => {
+            entry.grant_access(domid, frame, readonly);
+            Ok(())
+        }
+    })
+}
+
+/// Let `domid` access `len` bytes at `offset` in our frame `frame`.
+///
+/// Needs grant table v2 (EOPNOTSUPP otherwise). Sub-page grants can only
+/// be copied from/to, not mapped.
+pub fn grant_sub_page(
+    domid: DomId,
+    frame: u64,
+    offset: u16,
+    len: u16,
+    readonly: bool,
+) -> Result<GrantRef, XenError> {
+    grant_with(|entry| match entry {
+        GrantEntry::V1(_) => Err(XenError::OpNotSupp),
+        GrantEntry::V2(entry, _) => entry.grant_sub_page(domid, frame, offset, len, readonly),
+    })
+}
+
+/// Let `domid` access the frame granted to us as `trans_gref` by
+/// `trans_domid`.
+///
+/// Needs grant table v2 (EOPNOTSUPP otherwise). Transitive grants can only
+/// be copied from/to, not mapped.
+pub fn grant_transitive(
+    domid: DomId,
+    trans_domid: DomId,
+    trans_gref: GrantRef,
+) -> Result<GrantRef, XenError> {
+    grant_with(|entry| match entry {
+        GrantEntry::V1(_) => Err(XenError::OpNotSupp),
+        GrantEntry::V2(entry, _) => {
+            entry.grant_transitive(domid, trans_domid, trans_gref);
+            Ok(())
+        }
+    })
+}
+
+/// Revoke the access given by `gref` and release it.
+///
+/// Fails with EBUSY if the remote domain is still using the frame, in which
+/// case `gref` stays allocated and the call should be retried later.
+pub fn end_access(gref: GrantRef) -> Result<(), XenError> {
+    match table()?.entry(gref).ok_or(XenError::Inval)? {
+        GrantEntry::V1(entry) => entry.end_access(),
+        GrantEntry::V2(entry, status) => entry.end_access(status),
+    }?;
+
+    GRANT_REFS.free(gref);
+
+    Ok(())
+}
@@ -0,0 +1,155 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Grant table v1 entries (struct grant_entry_v1)
+
+use core::sync::atomic::{AtomicU16, AtomicU32, Ordering};
+
+use crate::xen::{DomId, XenError};
+
+use super::{GrantFlags, GrantType};
+
+/// Number of v1 entries in a grant table frame.
+pub const GRANT_ENTRIES_PER_FRAME: usize = 4096 / size_of::<GrantEntryV1>();
+
+#[repr(C)]
+pub struct GrantEntryV1 {
+    pub flags: AtomicU16,
+    pub domid: AtomicU16,
+    pub frame: AtomicU32,
+}
+
+const _: () = assert!(size_of::<GrantEntryV1>() == 8);
+
+pub type GrantFrameV1 = [GrantEntryV1; GRANT_ENTRIES_PER_FRAME];
+
+impl GrantEntryV1 {
+    /// Let `domid` access `frame`.
+    pub fn grant_access(&self, domid: DomId, frame: u64, readonly: bool) -> Result<(), XenError> {
+        let frame = u32::try_from(frame).map_err(|_| XenError::Overflow)?;
+
+        let mut flags = GrantFlags::empty();
+        if readonly {
+            flags |= GrantFlags::READONLY;
+        }
+
+        self.domid.store(domid.0, Ordering::Relaxed);
+        self.frame.store(frame, Ordering::Relaxed);
+        // Publish domid and frame before the entry becomes valid.
+        self.flags
+            .store(GrantType::PermitAccess.with(flags), Ordering::Release);
+
+        Ok(())
+    }
+
+    /// Revoke the access, fails with EBUSY while the remote domain still
+    /// has the frame mapped or is copying from/to it.
+    pub fn end_access(&self) -> Result<(), XenError> {
+        let mut flags = self.flags.load(Ordering::Acquire);
+
+        loop {
+            if GrantFlags::from_bits_retain(flags)
+                .intersects(GrantFlags::READING | GrantFlags::WRITING)
+            {
+                return Err(XenError::Busy);
+            }
+
+            match self
+                .flags
+                .compare_exchange(flags, 0, Ordering::AcqRel, Ordering::Acquire)
+            {
+                Ok(_) => return Ok(()),
+                Err(current) => flags = current,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xen::grant::{GrantRef, alloc::GrantRefAllocator};
+
+    fn table(len: usize) -> Vec<GrantEntryV1> {
+        (0..len)
+            .map(|_| GrantEntryV1 {
+                flags: AtomicU16::new(0),
+                domid: AtomicU16::new(0),
+                frame: AtomicU32::new(0),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn grant_access() {
+        let table = table(1);
+        let entry = &table[0];
+
+        entry.grant_access(DomId(3), 0x1234, true).unwrap();
+
+        assert_eq!(entry.domid.load(Ordering::Relaxed), 3);
+        assert_eq!(entry.frame.load(Ordering::Relaxed), 0x1234);
+
+        let flags = entry.flags.load(Ordering::Relaxed);
+        assert_eq!(GrantType::from_flags(flags), GrantType::PermitAccess);
+        assert_eq!(GrantFlags::from_bits_truncate(flags), GrantFlags::READONLY);
+
+        // Frames past 32 bits can't be granted with v1.
+        assert_eq!(
+            entry.grant_access(DomId(3), 1 << 32, false),
+            Err(XenError::Overflow)
+        );
+    }
+
+    #[test]
+    fn end_access_busy() {
+        let table = table(1);
+        let entry = &table[0];
+
+        entry.grant_access(DomId(0), 42, false).unwrap();
+
+        for busy in [GrantFlags::READING, GrantFlags::WRITING] {
+            // As Xen does while the frame is mapped.
+            entry.flags.fetch_or(busy.bits(), Ordering::Relaxed);
+            assert_eq!(entry.end_access(), Err(XenError::Busy));
+            assert_ne!(entry.flags.load(Ordering::Relaxed), 0);
+
+            entry.flags.fetch_and(!busy.bits(), Ordering::Relaxed);
+        }
+
+        assert_eq!(entry.end_access(), Ok(()));
+        assert_eq!(entry.flags.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn grant_and_recycle() {
+        let table = table(16);
+        let refs = GrantRefAllocator::<1>::new();
+        let limit = table.len() as u32;
+
+        let grefs: Vec<_> = (0..8)
+            .map(|frame| {
+                let gref = refs.alloc(limit).unwrap();
+                table[gref.0 as usize]
+                    .grant_access(DomId(1), frame, false)
+                    .unwrap();
+                gref
+            })
+            .collect();
+
+        assert_eq!(refs.alloc(limit), Err(XenError::NoSpc));
+
+        // Still mapped by the backend, so the reference stays allocated.
+        let busy = &table[grefs[2].0 as usize];
+        busy.flags
+            .fetch_or(GrantFlags::READING.bits(), Ordering::Relaxed);
+        assert_eq!(busy.end_access(), Err(XenError::Busy));
+        assert_eq!(refs.alloc(limit), Err(XenError::NoSpc));
+
+        table[grefs[5].0 as usize].end_access().unwrap();
+        refs.free(grefs[5]);
+
+        assert_eq!(refs.alloc(limit), Ok(grefs[5]));
+        assert_eq!(grefs[0], GrantRef(8));
+    }
+}
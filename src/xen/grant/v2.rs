@@ -0,0 +1,118 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Grant table v2 entries (union grant_entry_v2)
+//!
+//! Unlike v1, the flags updated by Xen (READING/WRITING) live in separate
+//! status frames, one grant_status_t per entry.
+
+use core::sync::atomic::{AtomicU16, AtomicU32, AtomicU64, Ordering, fence};
+
+use crate::xen::{DomId, XenError};
+
+use super::{GrantFlags, GrantRef, GrantType};
+
+/// Number of v2 entries in a grant table frame.
+pub const GRANT_ENTRIES_PER_FRAME_V2: usize = 4096 / size_of::<GrantEntryV2>();
+
+/// Number of grant_status_t in a status frame.
+pub const GRANT_STATUS_PER_FRAME: usize = 4096 / size_of::<u16>();
+
+/// union grant_entry_v2
+///
+/// `word` holds page_off/length of sub-page entries and trans_domid of
+/// transitive ones, `frame` the frame (or the grant reference of transitive
+/// entries, in its low 32 bits).
+#[repr(C)]
+pub struct GrantEntryV2 {
+    pub flags: AtomicU16,
+    pub domid: AtomicU16,
+    pub word: AtomicU32,
+    pub frame: AtomicU64,
+}
+
+const _: () = assert!(size_of::<GrantEntryV2>() == 16);
+
+pub type GrantFrameV2 = [GrantEntryV2; GRANT_ENTRIES_PER_FRAME_V2];
+
+pub type GrantStatusFrame = [AtomicU16; GRANT_STATUS_PER_FRAME];
+
+impl GrantEntryV2 {
+    fn publish(&self, domid: DomId, word: u32, frame: u64, ty: GrantType, flags: GrantFlags) {
+        self.domid.store(domid.0, Ordering::Relaxed);
+        self.word.store(word, Ordering::Relaxed);
+        self.frame.store(frame, Ordering::Relaxed);
+        // Publish the entry content before it becomes valid.
+        self.flags.store(ty.with(flags), Ordering::Release);
+    }
+
+    fn access_flags(readonly: bool) -> GrantFlags {
+        match readonly {
+            true => GrantFlags::READONLY,
+            false => GrantFlags::empty(),
+        }
+    }
+
+    /// Let `domid` access `frame`.
+    pub fn grant_access(&self, domid: DomId, frame: u64, readonly: bool) {
+        self.publish(
+            domid,
+            0,
+            frame,
+            GrantType::PermitAccess,
+            Self::access_flags(readonly),
+        );
+    }
+
+    /// Let `domid` access `len` bytes at `offset` in `frame`.
+    pub fn grant_sub_page(
+        &self,
+        domid: DomId,
+        frame: u64,
+        offset: u16,
+        len: u16,
+        readonly: bool,
+    ) -> Result<(), XenError> {
+        if offset as usize + len as usize > 4096 {
+            return Err(XenError::Inval);
+        }
+
+        self.publish(
+            domid,
+            offset as u32 | (len as u32) << 16,
+            frame,
+            GrantType::PermitAccess,
+            Self::access_flags(readonly) | GrantFlags::SUB_PAGE,
+        );
+
+        Ok(())
+    }
+
+    /// Let `domid` access what grant `trans_gref` of `trans_domid` gives us.
+    pub fn grant_transitive(&self, domid: DomId, trans_domid: DomId, trans_gref: GrantRef) {
+        self.publish(
+            domid,
+            trans_domid.0 as u32,
+            trans_gref.0 as u64,
+            GrantType::Transitive,
+            GrantFlags::empty(),
+        );
+    }
+
+    /// Revoke the access, fails with EBUSY while the remote domain still
+    /// has the frame mapped or is copying from/to it (according to
+    /// `status`), in which case this should be retried later.
+    pub fn end_access(&self, status: &AtomicU16) -> Result<(), XenError> {
+        self.flags.store(0, Ordering::Relaxed);
+        // Xen checks the flags before updating the status.
+        fence(Ordering::SeqCst);
+
+        if GrantFlags::from_bits_retain(status.load(Ordering::Acquire))
+            .intersects(GrantFlags::READING | GrantFlags::WRITING)
+        {
+            return Err(XenError::Busy);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,108 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! HVM parameters (xen/include/public/hvm/params.h)
+
+use crate::xen::{
+    DomId, XenError,
+    hypercall::{call, xen_op},
+};
+
+pub(crate) const HVM_OP: usize = 34;
+
+const HVMOP_SET_PARAM: usize = 0;
+const HVMOP_GET_PARAM: usize = 1;
+
+/// HVM_PARAM_* indexes
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum HvmParam {
+    /// Event delivery method (see HVM_PARAM_CALLBACK_TYPE_*)
+    CallbackIrq = 0,
+    /// XenStore ring frame
+    StorePfn = 1,
+    /// XenStore event channel
+    StoreEvtchn = 2,
+    PaeEnabled = 4,
+    /// Synchronous ioreq frame (legacy device model interface)
+    IoreqPfn = 5,
+    /// Buffered ioreq frame (legacy device model interface)
+    BufioreqPfn = 6,
+    /// Viridian enlightenments
+    Viridian = 9,
+    TimerMode = 10,
+    HpetEnabled = 11,
+    /// Identity mapped page table used for unpaged real mode
+    IdentPt = 12,
+    AcpiSState = 14,
+    Vm86Tss = 15,
+    VptAlign = 16,
+    /// Console ring frame
+    ConsolePfn = 17,
+    /// Console event channel
+    ConsoleEvtchn = 18,
+    AcpiIoportsLocation = 19,
+    NestedHvm = 24,
+    BufioreqEvtchn = 26,
+    PagingRingPfn = 27,
+    MonitorRingPfn = 28,
+    SharingRingPfn = 29,
+    TripleFaultReason = 31,
+    /// First frame reserved for ioreq servers
+    IoreqServerPfn = 32,
+    /// Number of frames reserved for ioreq servers
+    NrIoreqServerPages = 33,
+    VmGenerationIdAddr = 34,
+    /// Alternate p2m mode
+    Altp2m = 35,
+    X87FipWidth = 36,
+    Vm86TssSized = 37,
+    McaCap = 38,
+}
+
+xen_op! {
+    #[derive(Clone, Copy, Default)]
+    struct XenHvmParam(HVM_OP) {
+        pub domid: u16 => in(0),
+        pub _pad: u16,
+        pub index: u32 => in(3),
+        pub value: u64 => inout(4),
+    }
+}
+
+/// Read `param` of domain `domid`.
+pub fn get_domain_param(domid: DomId, param: HvmParam) -> Result<u64, XenError> {
+    let mut op = XenHvmParam {
+        domid: domid.0,
+        index: param as u32,
+        ..Default::default()
+    };
+
+    unsafe { call(HVMOP_GET_PARAM, &mut op) }?;
+
+    Ok(op.value)
+}
+
+/// Write `param` of domain `domid`.
+pub fn set_domain_param(domid: DomId, param: HvmParam, value: u64) -> Result<(), XenError> {
+    let mut op = XenHvmParam {
+        domid: domid.0,
+        index: param as u32,
+        value,
+        ..Default::default()
+    };
+
+    unsafe { call(HVMOP_SET_PARAM, &mut op) }?;
+
+    Ok(())
+}
+
+/// Read `param` of the current domain.
+pub fn get_param(param: HvmParam) -> Result<u64, XenError> {
+    get_domain_param(DomId::SELF, param)
+}
+
+/// Write `param` of the current domain.
+pub fn set_param(param: HvmParam, value: u64) -> Result<(), XenError> {
+    set_domain_param(DomId::SELF, param, value)
+}
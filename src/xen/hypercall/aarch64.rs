@@ -1,111 +1,132 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright (C) 2025 Vates SAS - Teddy Astie
 
-use core::arch::asm;
-
-const XEN_IMM: u16 = 0xEA1;
+//! Arm64 Xen guest hypercall ABI
+//!
+//! Hypercalls are issued with `hvc #0xEA1`. The hypercall number is passed
+//! in x16, the arguments in x0-x4 and the result is returned in x0. Argument
+//! registers and x16 are clobbered by Xen.
+
+/// Perform a hypercall, `cmd` is put in x16 and the remaining operands are
+/// given to `asm!`.
+///
+/// The call is recorded by [`stats`](super::stats) when enabled.
+#[macro_export]
+macro_rules! native_hypercall {
+    ($cmd:expr, $($t:tt)*) => {{
+        let cmd: usize = $cmd;
+        let start = $crate::xen::hypercall::stats::begin();
+
+        core::arch::asm!("hvc #0xEA1", inout("x16") cmd => _, $($t)*);
+
+        $crate::xen::hypercall::stats::end(cmd, start);
+    }};
+}
 
 #[inline(always)]
-pub unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
+pub(super) unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
     let output: usize;
 
-    asm!(
-        "hvc #{XEN_IMM}",
-        in("x0") cmd,
-        lateout("x0") output,
-        inout("x1") param[0] => _,
-        inout("x2") param[1] => _,
-        inout("x3") param[2] => _,
-        inout("x4") param[3] => _,
-        inout("x5") param[4] => _,
-        inout("x16") 0 => _,
-        XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("x0") param[0] => output,
+            inout("x1") param[1] => _,
+            inout("x2") param[2] => _,
+            inout("x3") param[3] => _,
+            inout("x4") param[4] => _,
+        );
+    }
 
     output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
+pub(super) unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
     let output: usize;
 
-    asm!(
-        "hvc #{XEN_IMM}",
-        in("x0") cmd,
-        lateout("x0") output,
-        inout("x1") param[0] => _,
-        inout("x2") param[1] => _,
-        inout("x3") param[2] => _,
-        inout("x4") param[3] => _,
-        inout("x16") 0 => _,
-        XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("x0") param[0] => output,
+            inout("x1") param[1] => _,
+            inout("x2") param[2] => _,
+            inout("x3") param[3] => _,
+            out("x4") _,
+        );
+    }
 
     output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
+pub(super) unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
     let output: usize;
 
-    asm!(
-      "hvc #{XEN_IMM}",
-      in("x0") cmd,
-      lateout("x0") output,
-      inout("x1") param[0] => _,
-      inout("x2") param[1] => _,
-      inout("x3") param[2] => _,
-      inout("x16") 0 => _,
-      XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("x0") param[0] => output,
+            inout("x1") param[1] => _,
+            inout("x2") param[2] => _,
+            out("x3") _,
+            out("x4") _,
+        );
+    }
 
     output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
+pub(super) unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
     let output: usize;
 
-    asm!(
-        "hvc #{XEN_IMM}",
-        in("x0") cmd,
-        lateout("x0") output,
-        inout("x1") param[0] => _,
-        inout("x2") param[1] => _,
-        inout("x16") 0 => _,
-        XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("x0") param[0] => output,
+            inout("x1") param[1] => _,
+            out("x2") _,
+            out("x3") _,
+            out("x4") _,
+        );
+    }
 
     output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
+pub(super) unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
     let output: usize;
 
-    asm!(
-        "hvc #{XEN_IMM}",
-        in("x0") cmd,
-        lateout("x0") output,
-        inout("x1") param => _,
-        inout("x16") 0 => _,
-        XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("x0") param => output,
+            out("x1") _,
+            out("x2") _,
+            out("x3") _,
+            out("x4") _,
+        );
+    }
 
     output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall0(cmd: usize) -> usize {
+pub(super) unsafe fn hypercall0(cmd: usize) -> usize {
     let output: usize;
 
-    asm!(
-        "hvc #{XEN_IMM}",
-        in("x0") cmd,
-        lateout("x0") output,
-        inout("x16") 0 => _,
-        XEN_IMM = const XEN_IMM,
-    );
+    unsafe {
+        native_hypercall!(
+            cmd,
+            lateout("x0") output,
+            out("x1") _,
+            out("x2") _,
+            out("x3") _,
+            out("x4") _,
+        );
+    }
 
     output
 }
@@ -0,0 +1,206 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Hypercall argument ABIs
+//!
+//! Most hypercalls take a sub-operation and a pointer to an argument
+//! structure. With the `fastabi` feature (x86 only), the structure is
+//! instead passed in registers: the sub-operation goes in the first argument
+//! register and each field is given a slot among the following ones.
+//!
+//! Operations are declared once with [`xen_op!`], which generates both the
+//! `#[repr(C)]` structure and its register marshalling, and are issued
+//! with [`call`].
+
+use crate::xen::XenError;
+
+/// Flag set in the hypercall number to select the fast register ABI.
+pub const FASTABI_MASK: usize = 0x4000_0000;
+
+/// Number of fast ABI argument registers available to an operation (the
+/// sub-operation excluded).
+pub const FAST_ARGS: usize = 5;
+
+/// A hypercall sub-operation taking a single argument structure.
+///
+/// # Safety
+///
+/// Implementors must be `#[repr(C)]` and match the layout of the structure
+/// Xen expects for `HYPERCALL`. Use [`xen_op!`] to implement it.
+pub unsafe trait XenOp {
+    /// Hypercall number.
+    const HYPERCALL: usize;
+
+    /// Registers given to the fast ABI.
+    fn pack_regs(&self) -> [usize; FAST_ARGS];
+
+    /// Update fields from the registers returned by the fast ABI.
+    fn unpack_regs(&mut self, regs: &[usize; FAST_ARGS]);
+}
+
+/// Declare a `#[repr(C)]` hypercall argument structure implementing
+/// [`XenOp`].
+///
+/// Each field may be bound to a fast ABI register slot, with `in(n)`
+/// (given to Xen), `out(n)` (returned by Xen) or `inout(n)`.
+///
+/// ```ignore
+/// xen_op! {
+///     #[derive(Default)]
+///     struct XenHvmParam(HVM_OP) {
+///         domid: u16 => in(0),
+///         _pad: u16,
+///         index: u32 => in(3),
+///         value: u64 => inout(4),
+///     }
+/// }
+/// ```
+macro_rules! xen_op {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($hypercall:expr) {
+            $(
+                $(#[$fmeta:meta])*
+                $fvis:vis $field:ident : $ty:ty $(=> $dir:ident($slot:literal))?
+            ),* $(,)?
+        }
+    ) => {
+        $(#[$meta])*
+        #[repr(C)]
+        $vis struct $name {
+            $($(#[$fmeta])* $fvis $field: $ty,)*
+        }
+
+        unsafe impl $crate::xen::hypercall::XenOp for $name {
+            const HYPERCALL: usize = $hypercall;
+
+            #[allow(unused_mut)]
+            fn pack_regs(&self) -> [usize; $crate::xen::hypercall::FAST_ARGS] {
+                let mut regs = [0; $crate::xen::hypercall::FAST_ARGS];
+                $($($crate::xen::hypercall::xen_op!(@in $dir, regs[$slot], self.$field);)?)*
+                regs
+            }
+
+            #[allow(unused_variables)]
+            fn unpack_regs(&mut self, regs: &[usize; $crate::xen::hypercall::FAST_ARGS]) {
+                $($($crate::xen::hypercall::xen_op!(@out $dir, self.$field, regs[$slot], $ty);)?)*
+            }
+        }
+    };
+
+    (@in in, $reg:expr, $value:expr) => { $reg = $value as usize };
+    (@in inout, $reg:expr, $value:expr) => { $reg = $value as usize };
+    (@in out, $reg:expr, $value:expr) => {};
+
+    (@out in, $field:expr, $reg:expr, $ty:ty) => {};
+    (@out inout, $field:expr, $reg:expr, $ty:ty) => { $field = $reg as $ty };
+    (@out out, $field:expr, $reg:expr, $ty:ty) => { $field = $reg as $ty };
+}
+
+pub(crate) use xen_op;
+
+/// Issue `subop` of `O::HYPERCALL` through the fast register ABI.
+///
+/// # Safety
+///
+/// `subop` and `op` must form a valid hypercall, and any guest handle
+/// in `op` must be valid for the accesses Xen is going to make.
+#[cfg(all(feature = "fastabi", target_arch = "x86_64"))]
+pub unsafe fn call<O: XenOp>(subop: usize, op: &mut O) -> Result<usize, XenError> {
+    use super::{arch, ensure_xen};
+
+    ensure_xen()?;
+
+    let mut regs = [0; FAST_ARGS + 1];
+    regs[0] = subop;
+    regs[1..].copy_from_slice(&op.pack_regs());
+
+    let ret = XenError::from_ret(unsafe {
+        arch::hypercall_fast(O::HYPERCALL | FASTABI_MASK, &mut regs)
+    })?;
+
+    op.unpack_regs(regs[1..].try_into().unwrap());
+
+    Ok(ret)
+}
+
+/// Issue `subop` of `O::HYPERCALL`, passing `op` by address.
+///
+/// # Safety
+///
+/// `subop` and `op` must form a valid hypercall, and any guest handle
+/// in `op` must be valid for the accesses Xen is going to make.
+#[cfg(not(all(feature = "fastabi", target_arch = "x86_64")))]
+pub unsafe fn call<O: XenOp>(subop: usize, op: &mut O) -> Result<usize, XenError> {
+    use super::hypercall2;
+    use core::ptr;
+
+    unsafe { hypercall2(O::HYPERCALL, [subop, ptr::from_mut(op).addr()]) }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::offset_of;
+
+    use super::*;
+
+    xen_op! {
+        #[derive(Default)]
+        struct TestOp(17) {
+            domid: u16 => in(0),
+            _pad: u16,
+            index: u32 => in(3),
+            value: u64 => inout(4),
+            port: u32 => out(1),
+            flags: u8 => inout(2),
+        }
+    }
+
+    #[test]
+    fn memory_layout() {
+        assert_eq!(offset_of!(TestOp, domid), 0);
+        assert_eq!(offset_of!(TestOp, _pad), 2);
+        assert_eq!(offset_of!(TestOp, index), 4);
+        assert_eq!(offset_of!(TestOp, value), 8);
+        assert_eq!(offset_of!(TestOp, port), 16);
+        assert_eq!(offset_of!(TestOp, flags), 20);
+        assert_eq!(size_of::<TestOp>(), 24);
+        assert_eq!(TestOp::HYPERCALL, 17);
+    }
+
+    #[test]
+    fn pack_regs() {
+        let op = TestOp {
+            domid: 0x7FF0,
+            _pad: 0xFFFF,
+            index: 42,
+            value: 0x1234_5678_9ABC,
+            port: 5,
+            flags: 3,
+        };
+
+        // Padding and out fields are not given to Xen.
+        assert_eq!(op.pack_regs(), [0x7FF0, 0, 3, 42, 0x1234_5678_9ABC]);
+    }
+
+    #[test]
+    fn unpack_regs() {
+        let mut op = TestOp {
+            domid: 1,
+            index: 2,
+            value: 3,
+            port: 4,
+            flags: 5,
+            ..Default::default()
+        };
+
+        op.unpack_regs(&[10, 20, 0x1FF, 40, 50]);
+
+        // Only out and inout fields are updated, truncated to their type.
+        assert_eq!(op.domid, 1);
+        assert_eq!(op.index, 2);
+        assert_eq!(op.port, 20);
+        assert_eq!(op.flags, 0xFF);
+        assert_eq!(op.value, 50);
+    }
+}
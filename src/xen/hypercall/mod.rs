@@ -1,17 +1,117 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+use crate::xen::{XenError, detect::is_present};
+
+#[cfg(target_arch = "x86_64")]
+use crate::xen::detect;
+
+mod abi;
+pub mod stats;
+
+pub(crate) use abi::xen_op;
+pub use abi::{FAST_ARGS, FASTABI_MASK, XenOp, call};
+
 #[cfg(target_arch = "x86_64")]
 mod x86_64;
 
 #[cfg(target_arch = "x86_64")]
-pub use x86_64::*;
+use x86_64 as arch;
+
+#[cfg(target_arch = "x86_64")]
+pub use x86_64::hypercall_page_entry;
 
 #[cfg(target_arch = "aarch64")]
 mod aarch64;
 
 #[cfg(target_arch = "aarch64")]
-pub use aarch64::*;
+use aarch64 as arch;
 
 #[cfg(target_arch = "riscv64")]
 mod riscv64;
 
 #[cfg(target_arch = "riscv64")]
-pub use riscv64::*;
+use riscv64 as arch;
+
+/// Pick how hypercalls are issued.
+///
+/// On x86, the Xen hypercall page is registered when available, which
+/// avoids relying on the CPU vendor to choose between vmcall and vmmcall.
+pub fn init() {
+    #[cfg(target_arch = "x86_64")]
+    if let Some(info) = detect() {
+        arch::setup_hypercall_page(&info);
+    }
+}
+
+/// Fail with ENOSYS unless Xen has been detected, as hypercalls would
+/// otherwise fault.
+#[inline(always)]
+pub fn ensure_xen() -> Result<(), XenError> {
+    if is_present() {
+        Ok(())
+    } else {
+        Err(XenError::NoSys)
+    }
+}
+
+/// # Safety
+///
+/// `cmd` and `param` must form a valid hypercall, any pointer passed in
+/// `param` must be valid for the accesses Xen is going to make.
+#[inline(always)]
+pub unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall5(cmd, param) })
+}
+
+/// # Safety
+///
+/// See [`hypercall5`].
+#[inline(always)]
+pub unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall4(cmd, param) })
+}
+
+/// # Safety
+///
+/// See [`hypercall5`].
+#[inline(always)]
+pub unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall3(cmd, param) })
+}
+
+/// # Safety
+///
+/// See [`hypercall5`].
+#[inline(always)]
+pub unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall2(cmd, param) })
+}
+
+/// # Safety
+///
+/// See [`hypercall5`].
+#[inline(always)]
+pub unsafe fn hypercall1(cmd: usize, param: usize) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall1(cmd, param) })
+}
+
+/// # Safety
+///
+/// See [`hypercall5`].
+#[inline(always)]
+pub unsafe fn hypercall0(cmd: usize) -> Result<usize, XenError> {
+    ensure_xen()?;
+
+    XenError::from_ret(unsafe { arch::hypercall0(cmd) })
+}
@@ -0,0 +1,147 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Hypercall batching (`__HYPERVISOR_multicall`)
+//!
+//! Queued operations are submitted with a single hypercall (and thus a
+//! single VM exit), each entry getting its own result.
+
+use core::{marker::PhantomData, ptr};
+
+use super::{XenOp, hypercall2};
+use crate::xen::XenError;
+
+const MULTICALL: usize = 13;
+
+/// Maximum number of arguments of a multicall entry.
+pub const MULTICALL_MAX_ARGS: usize = 6;
+
+#[derive(Clone, Copy, Default)]
+#[repr(C)]
+struct MulticallEntry {
+    op: u64,
+    result: i64,
+    args: [u64; MULTICALL_MAX_ARGS],
+}
+
+/// Batch of up to `N` hypercalls.
+///
+/// Operations queued with [`Multicall::push`] stay borrowed until the batch
+/// is submitted.
+pub struct Multicall<'a, const N: usize> {
+    entries: [MulticallEntry; N],
+    len: usize,
+    _ops: PhantomData<&'a mut ()>,
+}
+
+impl<const N: usize> Default for Multicall<'_, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<'a, const N: usize> Multicall<'a, N> {
+    pub const fn new() -> Self {
+        Self {
+            entries: [MulticallEntry {
+                op: 0,
+                result: 0,
+                args: [0; _],
+            }; N],
+            len: 0,
+            _ops: PhantomData,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Queue hypercall `cmd` with raw arguments, returns the entry index.
+    ///
+    /// # Safety
+    ///
+    /// `cmd` and `args` must form a valid hypercall, and any pointer in
+    /// `args` must stay valid until the batch is submitted.
+    pub unsafe fn push_raw(&mut self, cmd: usize, args: &[usize]) -> Result<usize, XenError> {
+        if args.len() > MULTICALL_MAX_ARGS {
+            return Err(XenError::TooBig);
+        }
+
+        let index = self.len;
+        let entry = self.entries.get_mut(index).ok_or(XenError::NoSpc)?;
+
+        entry.op = cmd as u64;
+        entry.result = 0;
+        entry.args = [0; _];
+        for (arg, &value) in entry.args.iter_mut().zip(args) {
+            *arg = value as u64;
+        }
+
+        self.len += 1;
+        Ok(index)
+    }
+
+    /// Queue `subop` of `O::HYPERCALL`, returns the entry index.
+    ///
+    /// `op` is passed by address (regardless of the `fastabi` feature) and
+    /// is updated by Xen on submission.
+    ///
+    /// # Safety
+    ///
+    /// `subop` and `op` must form a valid hypercall, and any guest handle
+    /// in `op` must stay valid until the batch is submitted.
+    pub unsafe fn push<O: XenOp>(&mut self, subop: usize, op: &'a mut O) -> Result<usize, XenError> {
+        unsafe { self.push_raw(O::HYPERCALL, &[subop, ptr::from_mut(op).addr()]) }
+    }
+
+    /// Issue all the queued hypercalls.
+    ///
+    /// The error of the multicall itself is returned, per entry results are
+    /// available through [`MulticallResults`].
+    pub fn submit(mut self) -> Result<MulticallResults<N>, XenError> {
+        if self.len > 0 {
+            unsafe {
+                hypercall2(
+                    MULTICALL,
+                    [ptr::from_mut(&mut self.entries).addr(), self.len],
+                )
+            }?;
+        }
+
+        Ok(MulticallResults {
+            results: self.entries.map(|entry| entry.result),
+            len: self.len,
+        })
+    }
+}
+
+/// Per entry results of a submitted [`Multicall`].
+pub struct MulticallResults<const N: usize> {
+    results: [i64; N],
+    len: usize,
+}
+
+impl<const N: usize> MulticallResults<N> {
+    /// Result of entry `index`, `None` if out of bounds.
+    pub fn get(&self, index: usize) -> Option<Result<usize, XenError>> {
+        (index < self.len).then(|| XenError::from_ret(self.results[index] as usize))
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = Result<usize, XenError>> + '_ {
+        self.results[..self.len]
+            .iter()
+            .map(|&result| XenError::from_ret(result as usize))
+    }
+
+    /// First failing entry, as (index, error).
+    pub fn first_error(&self) -> Option<(usize, XenError)> {
+        self.iter()
+            .enumerate()
+            .find_map(|(i, result)| result.err().map(|e| (i, e)))
+    }
+}
@@ -1,32 +1,138 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright (C) 2025 Vates SAS - Teddy Astie
 
+//! RISC-V Xen guest hypercall ABI
+//!
+//! Hypercalls are issued with `ecall` from VS-mode. The hypercall number is
+//! passed in a7, the arguments in a0-a5 and the result is returned in a0.
+//! Argument registers are clobbered by Xen.
+
+/// Perform a hypercall, `cmd` is put in a7 and the remaining operands are
+/// given to `asm!`.
+///
+/// The call is recorded by [`stats`](super::stats) when enabled.
+#[macro_export]
+macro_rules! native_hypercall {
+    ($cmd:expr, $($t:tt)*) => {{
+        let cmd: usize = $cmd;
+        let start = $crate::xen::hypercall::stats::begin();
+
+        core::arch::asm!("ecall", in("a7") cmd, $($t)*);
+
+        $crate::xen::hypercall::stats::end(cmd, start);
+    }};
+}
+
 #[inline(always)]
-pub unsafe fn hypercall5(_cmd: usize, _param: [usize; 5]) -> usize {
-    0
+pub(super) unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("a0") param[0] => output,
+            inout("a1") param[1] => _,
+            inout("a2") param[2] => _,
+            inout("a3") param[3] => _,
+            inout("a4") param[4] => _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall4(_cmd: usize, _param: [usize; 4]) -> usize {
-    0
+pub(super) unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("a0") param[0] => output,
+            inout("a1") param[1] => _,
+            inout("a2") param[2] => _,
+            inout("a3") param[3] => _,
+            out("a4") _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall3(_cmd: usize, _param: [usize; 3]) -> usize {
-    0
+pub(super) unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("a0") param[0] => output,
+            inout("a1") param[1] => _,
+            inout("a2") param[2] => _,
+            out("a3") _,
+            out("a4") _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall2(_cmd: usize, _param: [usize; 2]) -> usize {
-    0
+pub(super) unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("a0") param[0] => output,
+            inout("a1") param[1] => _,
+            out("a2") _,
+            out("a3") _,
+            out("a4") _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall1(_cmd: usize, _param: usize) -> usize {
-    0
+pub(super) unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            inlateout("a0") param => output,
+            out("a1") _,
+            out("a2") _,
+            out("a3") _,
+            out("a4") _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
 
 #[inline(always)]
-pub unsafe fn hypercall0(_cmd: usize) -> usize {
-    0
+pub(super) unsafe fn hypercall0(cmd: usize) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+            cmd,
+            lateout("a0") output,
+            out("a1") _,
+            out("a2") _,
+            out("a3") _,
+            out("a4") _,
+            out("a5") _,
+        );
+    }
+
+    output
 }
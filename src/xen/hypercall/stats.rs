@@ -0,0 +1,181 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Hypercall tracing (`hypercall-stats` feature)
+//!
+//! Every hypercall issued through `native_hypercall!` is counted per
+//! hypercall number, and its latency (in TSC, CNTVCT or time CSR ticks) is
+//! recorded in a log2 histogram. Without the feature, the hooks compile to
+//! nothing.
+
+#[cfg(feature = "hypercall-stats")]
+use core::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of tracked hypercall numbers, higher ones share the last slot.
+#[cfg(feature = "hypercall-stats")]
+const NR_HYPERCALLS: usize = 64;
+
+/// Number of latency buckets, bucket `n` covers [2^n, 2^(n+1)) ticks.
+#[cfg(feature = "hypercall-stats")]
+const NR_BUCKETS: usize = 32;
+
+#[cfg(feature = "hypercall-stats")]
+struct HypercallStats {
+    count: AtomicU64,
+    total: AtomicU64,
+    max: AtomicU64,
+    histogram: [AtomicU64; NR_BUCKETS],
+}
+
+#[cfg(feature = "hypercall-stats")]
+impl HypercallStats {
+    const fn new() -> Self {
+        Self {
+            count: AtomicU64::new(0),
+            total: AtomicU64::new(0),
+            max: AtomicU64::new(0),
+            histogram: [const { AtomicU64::new(0) }; _],
+        }
+    }
+}
+
+#[cfg(feature = "hypercall-stats")]
+static STATS: [HypercallStats; NR_HYPERCALLS] = [const { HypercallStats::new() }; _];
+
+#[cfg(feature = "hypercall-stats")]
+fn name(nr: usize) -> &'static str {
+    match nr {
+        0 => "set_trap_table",
+        1 => "mmu_update",
+        2 => "set_gdt",
+        3 => "stack_switch",
+        4 => "set_callbacks",
+        5 => "fpu_taskswitch",
+        6 => "sched_op_compat",
+        7 => "platform_op",
+        8 => "set_debugreg",
+        9 => "get_debugreg",
+        10 => "update_descriptor",
+        12 => "memory_op",
+        13 => "multicall",
+        14 => "update_va_mapping",
+        15 => "set_timer_op",
+        16 => "event_channel_op_compat",
+        17 => "xen_version",
+        18 => "console_io",
+        19 => "physdev_op_compat",
+        20 => "grant_table_op",
+        21 => "vm_assist",
+        22 => "update_va_mapping_otherdomain",
+        23 => "iret",
+        24 => "vcpu_op",
+        25 => "set_segment_base",
+        26 => "mmuext_op",
+        27 => "xsm_op",
+        28 => "nmi_op",
+        29 => "sched_op",
+        30 => "callback_op",
+        31 => "xenoprof_op",
+        32 => "event_channel_op",
+        33 => "physdev_op",
+        34 => "hvm_op",
+        35 => "sysctl",
+        36 => "domctl",
+        37 => "kexec_op",
+        38 => "tmem_op",
+        39 => "argo_op",
+        40 => "xenpmu_op",
+        41 => "dm_op",
+        42 => "hypfs_op",
+        nr if nr >= NR_HYPERCALLS - 1 => "(other)",
+        _ => "(unknown)",
+    }
+}
+
+/// Start tracing a hypercall, returns the start timestamp.
+#[doc(hidden)]
+#[cfg(feature = "hypercall-stats")]
+#[inline(always)]
+pub fn begin() -> u64 {
+    crate::delay::read_tsc()
+}
+
+/// Record a hypercall started at `start`.
+#[doc(hidden)]
+#[cfg(feature = "hypercall-stats")]
+#[inline(always)]
+pub fn end(cmd: usize, start: u64) {
+    let latency = crate::delay::read_tsc().wrapping_sub(start);
+    let stats = &STATS[(cmd & !super::FASTABI_MASK).min(NR_HYPERCALLS - 1)];
+    let bucket = (latency.max(1).ilog2() as usize).min(NR_BUCKETS - 1);
+
+    stats.count.fetch_add(1, Ordering::Relaxed);
+    stats.total.fetch_add(latency, Ordering::Relaxed);
+    stats.max.fetch_max(latency, Ordering::Relaxed);
+    stats.histogram[bucket].fetch_add(1, Ordering::Relaxed);
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "hypercall-stats"))]
+#[inline(always)]
+pub fn begin() -> u64 {
+    0
+}
+
+#[doc(hidden)]
+#[cfg(not(feature = "hypercall-stats"))]
+#[inline(always)]
+pub fn end(_cmd: usize, _start: u64) {}
+
+/// Number of calls made to hypercall `nr`.
+#[cfg(feature = "hypercall-stats")]
+pub fn count(nr: usize) -> u64 {
+    STATS
+        .get(nr)
+        .map_or(0, |stats| stats.count.load(Ordering::Relaxed))
+}
+
+/// Clear all the counters.
+#[cfg(feature = "hypercall-stats")]
+pub fn reset() {
+    for stats in &STATS {
+        stats.count.store(0, Ordering::Relaxed);
+        stats.total.store(0, Ordering::Relaxed);
+        stats.max.store(0, Ordering::Relaxed);
+        stats
+            .histogram
+            .iter()
+            .for_each(|bucket| bucket.store(0, Ordering::Relaxed));
+    }
+}
+
+/// Print the counters and latency histograms on the console.
+#[cfg(feature = "hypercall-stats")]
+pub fn dump() {
+    println!("-- Hypercall statistics (ticks) --");
+    println!(
+        "{:>3} {:<24} {:>10} {:>10} {:>10}",
+        "nr", "hypercall", "count", "avg", "max"
+    );
+
+    for (nr, stats) in STATS.iter().enumerate() {
+        let count = stats.count.load(Ordering::Relaxed);
+        if count == 0 {
+            continue;
+        }
+
+        println!(
+            "{nr:>3} {:<24} {count:>10} {:>10} {:>10}",
+            name(nr),
+            stats.total.load(Ordering::Relaxed) / count,
+            stats.max.load(Ordering::Relaxed)
+        );
+
+        for (bucket, hits) in stats.histogram.iter().enumerate() {
+            let hits = hits.load(Ordering::Relaxed);
+            if hits != 0 {
+                println!("      [2^{bucket:<2}, 2^{:<2}) {hits}", bucket + 1);
+            }
+        }
+    }
+}
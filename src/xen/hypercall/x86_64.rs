@@ -1,23 +1,88 @@
 // SPDX-License-Identifier: Apache-2.0
 // Copyright (C) 2025 Vates SAS - Teddy Astie
 
+use core::{
+    cell::SyncUnsafeCell,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use x86_64::registers::model_specific::Msr;
+
+use crate::{arch::x86_64::sev::SEV_STATUS, xen::XenInfo};
+
+/// Size of a hypercall stub in the hypercall page.
+const HYPERCALL_STUB_SIZE: usize = 32;
+
+#[repr(C, align(4096))]
+struct HypercallPage([u8; 4096]);
+
+/// Filled by Xen, and executed: it lives in the code range, which is never
+/// mapped NX, rather than with the (possibly NX) data.
+#[unsafe(link_section = ".text.hypercall_page")]
+static HYPERCALL_PAGE: SyncUnsafeCell<HypercallPage> = SyncUnsafeCell::new(HypercallPage([0; _]));
+static HYPERCALL_PAGE_READY: AtomicBool = AtomicBool::new(false);
+
+/// Register the hypercall page through the MSR advertised by Xen.
+///
+/// Once registered, hypercalls go through the stubs written by Xen instead
+/// of picking between vmcall/vmmcall ourselves.
+pub(super) fn setup_hypercall_page(info: &XenInfo) -> bool {
+    // Xen can't write the stubs in encrypted memory.
+    // SAFETY: SEV_STATUS is only written during early boot.
+    if info.hypercall_pages == 0 || unsafe { SEV_STATUS } & 1 != 0 {
+        return false;
+    }
+
+    // SAFETY: HYPERCALL_PAGE is identity mapped, and executable as part of
+    // the code range.
+    unsafe { Msr::new(info.hypercall_msr).write(HYPERCALL_PAGE.get().addr() as u64) };
+    HYPERCALL_PAGE_READY.store(true, Ordering::Release);
+
+    true
+}
+
+/// Address of the hypercall page stub of `cmd`, if the hypercall page is in use.
+#[doc(hidden)]
+#[inline(always)]
+pub fn hypercall_page_entry(cmd: usize) -> Option<usize> {
+    (cmd < size_of::<HypercallPage>() / HYPERCALL_STUB_SIZE
+        && HYPERCALL_PAGE_READY.load(Ordering::Acquire))
+    .then(|| HYPERCALL_PAGE.get().addr() + cmd * HYPERCALL_STUB_SIZE)
+}
+
+/// Perform a hypercall, `cmd` is put in rax and the remaining operands are
+/// given to `asm!`.
+///
+/// Goes through the hypercall page when it is registered, otherwise uses
+/// vmcall or vmmcall depending on the CPU vendor. Commands outside of the
+/// hypercall page (e.g. fastabi ones) always use the instruction directly.
+///
+/// The call is recorded by [`stats`](super::stats) when enabled.
 #[macro_export]
 macro_rules! native_hypercall {
-    ($($t:tt)*) => {
-        match $crate::arch::x86_64::CPU_VENDOR {
-            crate::arch::x86_64::CpuVendor::Intel => core::arch::asm!("vmcall", $($t)*),
-            crate::arch::x86_64::CpuVendor::Amd => core::arch::asm!("vmmcall", $($t)*),
+    ($cmd:expr, $($t:tt)*) => {{
+        let cmd: usize = $cmd;
+        let start = $crate::xen::hypercall::stats::begin();
+
+        match $crate::xen::hypercall::hypercall_page_entry(cmd) {
+            Some(entry) => core::arch::asm!("call {entry}", entry = in(reg) entry, in("rax") cmd, $($t)*),
+            None => match $crate::arch::x86_64::CPU_VENDOR {
+                $crate::arch::x86_64::CpuVendor::Intel => core::arch::asm!("vmcall", in("rax") cmd, $($t)*),
+                $crate::arch::x86_64::CpuVendor::Amd => core::arch::asm!("vmmcall", in("rax") cmd, $($t)*),
+            },
         }
-    };
+
+        $crate::xen::hypercall::stats::end(cmd, start);
+    }};
 }
 
 #[inline(always)]
-pub unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
+pub(super) unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           in("rdi") param[0],
           in("rsi") param[1],
@@ -31,12 +96,12 @@ pub unsafe fn hypercall5(cmd: usize, param: [usize; 5]) -> usize {
 }
 
 #[inline(always)]
-pub unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
+pub(super) unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           in("rdi") param[0],
           in("rsi") param[1],
@@ -50,12 +115,12 @@ pub unsafe fn hypercall4(cmd: usize, param: [usize; 4]) -> usize {
 }
 
 #[inline(always)]
-pub unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
+pub(super) unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           in("rdi") param[0],
           in("rsi") param[1],
@@ -69,12 +134,12 @@ pub unsafe fn hypercall3(cmd: usize, param: [usize; 3]) -> usize {
 }
 
 #[inline(always)]
-pub unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
+pub(super) unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           in("rdi") param[0],
           in("rsi") param[1],
@@ -88,12 +153,12 @@ pub unsafe fn hypercall2(cmd: usize, param: [usize; 2]) -> usize {
 }
 
 #[inline(always)]
-pub unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
+pub(super) unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           in("rdi") param,
           out("rsi") _,
@@ -107,12 +172,12 @@ pub unsafe fn hypercall1(cmd: usize, param: usize) -> usize {
 }
 
 #[inline(always)]
-pub unsafe fn hypercall0(cmd: usize) -> usize {
+pub(super) unsafe fn hypercall0(cmd: usize) -> usize {
     let output: usize;
 
     unsafe {
         native_hypercall!(
-          in("rax") cmd,
+          cmd,
           lateout("rax") output,
           out("rdi") _,
           out("rsi") _,
@@ -123,3 +188,26 @@ pub unsafe fn hypercall0(cmd: usize) -> usize {
     }
     output
 }
+
+/// Fast ABI hypercall, `regs` holds rdi, rsi, rdx, r10, r8 and r9 and is
+/// updated with their values on return.
+#[cfg(feature = "fastabi")]
+#[inline(always)]
+pub(super) unsafe fn hypercall_fast(cmd: usize, regs: &mut [usize; 6]) -> usize {
+    let output: usize;
+
+    unsafe {
+        native_hypercall!(
+          cmd,
+          lateout("rax") output,
+          inout("rdi") regs[0],
+          inout("rsi") regs[1],
+          inout("rdx") regs[2],
+          inout("r10") regs[3],
+          inout("r8") regs[4],
+          inout("r9") regs[5],
+        );
+    }
+
+    output
+}
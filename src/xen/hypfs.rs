@@ -0,0 +1,310 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Hypervisor filesystem client (`__HYPERVISOR_hypfs_op`)
+//!
+//! Allows reading hypervisor runtime informations such as `/params/*` or
+//! `/buildinfo/config` without a toolstack.
+
+use core::{
+    ptr, str,
+    sync::atomic::{AtomicBool, Ordering},
+};
+
+use crate::xen::{XenError, hypercall::hypercall5};
+
+const HYPFS_OP: usize = 42;
+
+const XEN_HYPFS_OP_GET_VERSION: usize = 0;
+const XEN_HYPFS_OP_READ: usize = 1;
+
+/// Interface version implemented by this client.
+pub const XEN_HYPFS_VERSION: u32 = 1;
+
+/// Maximum length of a path, including the terminating NUL.
+pub const XEN_HYPFS_MAX_PATHLEN: usize = 1024;
+
+/// Size of struct xen_hypfs_direntry.
+const DIRENTRY_SIZE: usize = 12;
+/// Size of struct xen_hypfs_dirlistentry, without the name.
+const DIRLISTENTRY_SIZE: usize = DIRENTRY_SIZE + 2;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HypfsType {
+    Dir,
+    Blob,
+    String,
+    Uint,
+    Int,
+    Bool,
+    Unknown(u8),
+}
+
+impl From<u8> for HypfsType {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => HypfsType::Dir,
+            1 => HypfsType::Blob,
+            2 => HypfsType::String,
+            3 => HypfsType::Uint,
+            4 => HypfsType::Int,
+            5 => HypfsType::Bool,
+            t => HypfsType::Unknown(t),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HypfsEncoding {
+    Plain,
+    Gzip,
+    Unknown(u8),
+}
+
+impl From<u8> for HypfsEncoding {
+    fn from(value: u8) -> Self {
+        match value {
+            0 => HypfsEncoding::Plain,
+            1 => HypfsEncoding::Gzip,
+            e => HypfsEncoding::Unknown(e),
+        }
+    }
+}
+
+/// Description of an entry (struct xen_hypfs_direntry).
+#[derive(Clone, Copy, Debug)]
+pub struct HypfsDirentry {
+    pub entry_type: HypfsType,
+    pub encoding: HypfsEncoding,
+    pub content_len: u32,
+    /// Maximum size of a write, 0 if the entry is read-only.
+    pub max_write_len: u32,
+}
+
+impl HypfsDirentry {
+    fn parse(buf: &[u8]) -> Option<Self> {
+        let raw = buf.get(..DIRENTRY_SIZE)?;
+
+        Some(Self {
+            entry_type: raw[0].into(),
+            encoding: raw[1].into(),
+            content_len: u32::from_ne_bytes(raw[4..8].try_into().unwrap()),
+            max_write_len: u32::from_ne_bytes(raw[8..12].try_into().unwrap()),
+        })
+    }
+}
+
+/// Content of an entry.
+///
+/// Encoded (e.g. gzip) entries are always returned as [`HypfsValue::Blob`].
+#[derive(Clone, Copy, Debug)]
+pub enum HypfsValue<'a> {
+    Dir(HypfsDir<'a>),
+    Blob(&'a [u8]),
+    String(&'a str),
+    Uint(u64),
+    Int(i64),
+    Bool(bool),
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct HypfsEntry<'a> {
+    pub direntry: HypfsDirentry,
+    pub value: HypfsValue<'a>,
+}
+
+/// Directory listing, made of xen_hypfs_dirlistentry records.
+#[derive(Clone, Copy, Debug)]
+pub struct HypfsDir<'a> {
+    content: &'a [u8],
+}
+
+/// Entry of a directory listing.
+#[derive(Clone, Copy, Debug)]
+pub struct HypfsDirEntry<'a> {
+    pub direntry: HypfsDirentry,
+    pub name: &'a str,
+}
+
+impl<'a> HypfsDir<'a> {
+    pub fn iter(&self) -> HypfsDirIter<'a> {
+        HypfsDirIter {
+            remaining: self.content,
+        }
+    }
+}
+
+impl<'a> IntoIterator for HypfsDir<'a> {
+    type Item = HypfsDirEntry<'a>;
+    type IntoIter = HypfsDirIter<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct HypfsDirIter<'a> {
+    remaining: &'a [u8],
+}
+
+impl<'a> Iterator for HypfsDirIter<'a> {
+    type Item = HypfsDirEntry<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let direntry = HypfsDirentry::parse(self.remaining)?;
+        let off_next = u16::from_ne_bytes(
+            self.remaining
+                .get(DIRENTRY_SIZE..DIRLISTENTRY_SIZE)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+
+        let name = &self.remaining[DIRLISTENTRY_SIZE..];
+        let name = &name[..name.iter().position(|&c| c == 0).unwrap_or(name.len())];
+
+        // The last entry has off_next = 0.
+        self.remaining = match off_next {
+            0 => &[],
+            off => self.remaining.get(off..).unwrap_or(&[]),
+        };
+
+        Some(HypfsDirEntry {
+            direntry,
+            name: str::from_utf8(name).unwrap_or("(non-utf8)"),
+        })
+    }
+}
+
+fn parse_value<'a>(
+    direntry: &HypfsDirentry,
+    content: &'a [u8],
+) -> Result<HypfsValue<'a>, XenError> {
+    if direntry.encoding != HypfsEncoding::Plain {
+        return Ok(HypfsValue::Blob(content));
+    }
+
+    let value = match direntry.entry_type {
+        HypfsType::Dir => HypfsValue::Dir(HypfsDir { content }),
+        HypfsType::Blob | HypfsType::Unknown(_) => HypfsValue::Blob(content),
+        HypfsType::String => {
+            let len = content
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(content.len());
+            HypfsValue::String(str::from_utf8(&content[..len]).map_err(|_| XenError::IlSeq)?)
+        }
+        HypfsType::Uint => HypfsValue::Uint(match *content {
+            [v] => v as u64,
+            [_, _] => u16::from_ne_bytes(content.try_into().unwrap()) as u64,
+            [_, _, _, _] => u32::from_ne_bytes(content.try_into().unwrap()) as u64,
+            [_, _, _, _, _, _, _, _] => u64::from_ne_bytes(content.try_into().unwrap()),
+            _ => return Err(XenError::BadMsg),
+        }),
+        HypfsType::Int => HypfsValue::Int(match *content {
+            [v] => v as i8 as i64,
+            [_, _] => i16::from_ne_bytes(content.try_into().unwrap()) as i64,
+            [_, _, _, _] => i32::from_ne_bytes(content.try_into().unwrap()) as i64,
+            [_, _, _, _, _, _, _, _] => i64::from_ne_bytes(content.try_into().unwrap()),
+            _ => return Err(XenError::BadMsg),
+        }),
+        HypfsType::Bool => HypfsValue::Bool(content.iter().any(|&b| b != 0)),
+    };
+
+    Ok(value)
+}
+
+/// Version of the hypfs interface provided by Xen.
+pub fn get_version() -> Result<u32, XenError> {
+    let version = unsafe { hypercall5(HYPFS_OP, [XEN_HYPFS_OP_GET_VERSION, 0, 0, 0, 0]) }?;
+
+    Ok(version as u32)
+}
+
+/// Set once Xen is known to provide [`XEN_HYPFS_VERSION`].
+static VERSION_CHECKED: AtomicBool = AtomicBool::new(false);
+
+/// Fail with EOPNOTSUPP unless Xen provides the interface version whose
+/// records we parse.
+fn check_version() -> Result<(), XenError> {
+    if VERSION_CHECKED.load(Ordering::Relaxed) {
+        return Ok(());
+    }
+
+    let version = get_version()?;
+    if version != XEN_HYPFS_VERSION {
+        log::warn!("Unsupported hypfs interface version {version}");
+        return Err(XenError::OpNotSupp);
+    }
+
+    VERSION_CHECKED.store(true, Ordering::Relaxed);
+    Ok(())
+}
+
+/// Read the raw direntry and content of `path` into `buf`.
+///
+/// Fails with ENOBUFS if `buf` is too small, see [`entry_size`], and with
+/// EOPNOTSUPP if Xen provides another interface version.
+fn read_raw(path: &str, buf: &mut [u8]) -> Result<(), XenError> {
+    check_version()?;
+
+    let mut raw_path = [0u8; XEN_HYPFS_MAX_PATHLEN];
+
+    // Keep room for the terminating NUL.
+    if path.len() >= raw_path.len() {
+        return Err(XenError::NameTooLong);
+    }
+    raw_path[..path.len()].copy_from_slice(path.as_bytes());
+
+    unsafe {
+        hypercall5(
+            HYPFS_OP,
+            [
+                XEN_HYPFS_OP_READ,
+                ptr::from_ref(&raw_path).addr(),
+                path.len() + 1,
+                buf.as_mut_ptr().addr(),
+                buf.len(),
+            ],
+        )
+    }?;
+
+    Ok(())
+}
+
+/// Size of the buffer needed by [`read_entry`] for `path`.
+pub fn entry_size(path: &str) -> Result<usize, XenError> {
+    let mut buf = [0u8; DIRENTRY_SIZE];
+
+    match read_raw(path, &mut buf) {
+        // The direntry is still filled when the content doesn't fit.
+        Ok(()) | Err(XenError::NoBufs) => {}
+        Err(e) => return Err(e),
+    }
+
+    let direntry = HypfsDirentry::parse(&buf).ok_or(XenError::BadMsg)?;
+    Ok(DIRENTRY_SIZE + direntry.content_len as usize)
+}
+
+/// Read the entry at `path` (e.g. "/params/sched"), using `buf` as storage.
+pub fn read_entry<'a>(path: &str, buf: &'a mut [u8]) -> Result<HypfsEntry<'a>, XenError> {
+    read_raw(path, buf)?;
+
+    let buf = &*buf;
+    let direntry = HypfsDirentry::parse(buf).ok_or(XenError::NoBufs)?;
+    let content = buf
+        .get(DIRENTRY_SIZE..DIRENTRY_SIZE + direntry.content_len as usize)
+        .ok_or(XenError::NoBufs)?;
+
+    Ok(HypfsEntry {
+        direntry,
+        value: parse_value(&direntry, content)?,
+    })
+}
+
+/// List the directory at `path`, using `buf` as storage.
+pub fn read_dir<'a>(path: &str, buf: &'a mut [u8]) -> Result<HypfsDir<'a>, XenError> {
+    match read_entry(path, buf)?.value {
+        HypfsValue::Dir(dir) => Ok(dir),
+        _ => Err(XenError::NotDir),
+    }
+}
@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Guest physmap management (`__HYPERVISOR_memory_op`)
+
+use crate::xen::{
+    DomId, XenError,
+    hypercall::{call, hypercall2, xen_op},
+};
+
+pub(crate) const MEMORY_OP: usize = 12;
+
+const XENMEM_DECREASE_RESERVATION: usize = 1;
+const XENMEM_ADD_TO_PHYSMAP: usize = 7;
+
+/// Flag of `idx` selecting a grant table status frame (v2 only).
+pub const XENMAPIDX_GRANT_TABLE_STATUS: u64 = 1 << 31;
+
+/// XENMAPSPACE_* values
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u32)]
+pub enum XenMapSpace {
+    /// Shared info page (idx is ignored)
+    SharedInfo = 0,
+    /// Grant table frame `idx` (with XENMAPIDX_grant_table_status for v2
+    /// status frames)
+    GrantTable = 1,
+    /// Guest frame `idx`
+    Gmfn = 2,
+    /// Range of guest frames, see `size`
+    GmfnRange = 3,
+    /// Frame of a foreign domain
+    GmfnForeign = 4,
+    /// Device MMIO region (Arm only)
+    DevMmio = 5,
+}
+
+xen_op! {
+    #[derive(Clone, Copy, Default)]
+    struct XenAddToPhysmap(MEMORY_OP) {
+        pub domid: u16 => in(0),
+        pub size: u16 => in(1),
+        pub space: u32 => in(2),
+        pub idx: u64 => in(3),
+        pub gpfn: u64 => in(4),
+    }
+}
+
+/// Map frame `idx` of `space` at guest frame `gpfn` of the current domain.
+///
+/// Whatever was previously at `gpfn` is released by Xen.
+///
+/// # Safety
+///
+/// `gpfn` must not back memory still in use.
+pub unsafe fn add_to_physmap(space: XenMapSpace, idx: u64, gpfn: u64) -> Result<(), XenError> {
+    let mut op = XenAddToPhysmap {
+        domid: DomId::SELF.0,
+        space: space as u32,
+        idx,
+        gpfn,
+        ..Default::default()
+    };
+
+    unsafe { call(XENMEM_ADD_TO_PHYSMAP, &mut op) }?;
+
+    Ok(())
+}
+
+/// struct xen_memory_reservation
+#[repr(C)]
+#[derive(Default)]
+struct XenMemoryReservation {
+    extent_start: u64,
+    nr_extents: u64,
+    extent_order: u32,
+    mem_flags: u32,
+    domid: u16,
+}
+
+/// Give the guest frames `gpfns` of the current domain back to Xen, leaving
+/// holes in the physmap.
+///
+/// Returns the number of frames actually released.
+///
+/// # Safety
+///
+/// The frames must not back memory still in use.
+pub unsafe fn decrease_reservation(gpfns: &[u64]) -> Result<usize, XenError> {
+    let mut op = XenMemoryReservation {
+        extent_start: gpfns.as_ptr().addr() as u64,
+        nr_extents: gpfns.len() as u64,
+        domid: DomId::SELF.0,
+        ..Default::default()
+    };
+
+    unsafe {
+        hypercall2(
+            MEMORY_OP,
+            [XENMEM_DECREASE_RESERVATION, (&raw mut op).addr()],
+        )
+    }
+}
@@ -1,8 +1,9 @@
 //! Xen paravirtualized ring buffer utilities
-use core::{
-    cmp,
-    sync::atomic::{AtomicU32, Ordering},
-};
+//!
+//! Byte rings as used by the console and XenStore. The producer and consumer
+//! indexes are free-running (only masked when accessing the ring), so the
+//! ring length must be a power of two.
+use core::sync::atomic::{AtomicU32, Ordering};
 
 use volatile::VolatilePtr;
 
@@ -23,58 +24,70 @@ pub enum XenRingError {
     MisbehavingIndex,
 }
 
-#[inline(always)]
-fn available(prod: usize, cons: usize, len: usize) -> usize {
-    match prod.cmp(&cons) {
-        cmp::Ordering::Less => cons - prod - 1,
-        cmp::Ordering::Equal => len + cons - prod - 1,
-        cmp::Ordering::Greater => len - 1,
+impl XenRing<'_> {
+    /// Number of bytes the ring can hold, all of it as the indexes are
+    /// free-running.
+    pub fn capacity(&self) -> usize {
+        self.ring.len()
     }
-}
 
-#[inline(always)]
-fn queued(prod: usize, cons: usize, len: usize) -> usize {
-    len - available(prod, cons, len)
-}
+    /// Load both indexes, checking that they are consistent.
+    fn indexes(&self) -> Result<(u32, u32), XenRingError> {
+        let cons = self.cons.load(Ordering::Acquire);
+        let prod = self.prod.load(Ordering::Acquire);
 
-impl XenRing<'_> {
-    pub fn capacity(&self) -> usize {
-        self.ring.len() - 1
+        if prod.wrapping_sub(cons) as usize > self.ring.len() {
+            return Err(XenRingError::MisbehavingIndex);
+        }
+
+        Ok((prod, cons))
+    }
+
+    /// Number of bytes queued in the ring.
+    pub fn queued(&self) -> Result<usize, XenRingError> {
+        let (prod, cons) = self.indexes()?;
+
+        Ok(prod.wrapping_sub(cons) as usize)
     }
 
+    /// Number of bytes that can be written to the ring.
+    pub fn available(&self) -> Result<usize, XenRingError> {
+        Ok(self.ring.len() - self.queued()?)
+    }
+
+    /// Write the whole `buffer` as producer, or nothing if there isn't enough
+    /// room.
     pub fn write(&mut self, buffer: &[u8]) -> Result<(), XenRingError> {
-        if buffer.len() >= self.ring.len() {
+        let len = self.ring.len();
+
+        if buffer.len() > len {
             return Err(XenRingError::TooLarge);
         }
 
-        let cons = self.cons.load(Ordering::Acquire) as usize % self.ring.len();
-        let prod = self.prod.load(Ordering::Acquire) as usize % self.ring.len();
+        let (prod, cons) = self.indexes()?;
 
-        let dest_prod = (prod + buffer.len()) % self.ring.len();
-
-        if available(prod, cons, self.ring.len()) < buffer.len() {
+        if len - (prod.wrapping_sub(cons) as usize) < buffer.len() {
             return Err(XenRingError::NotReady);
         }
 
-        if prod < cons || buffer.len() <= (self.ring.len() - prod) {
-            self.ring.index(prod..dest_prod).copy_from_slice(buffer);
-        } else if prod >= cons {
-            /*
-             * Split the buffer in two parts, one that will be copied at
-             * the end of the ring buffer, another at the beginning.
-             *
-             * [(parts.1)C    P(parts.0)]
-             */
-
-            let parts = buffer.split_at(self.ring.len() - prod);
-            self.ring.index(prod..).copy_from_slice(parts.0);
-            self.ring.index(..dest_prod).copy_from_slice(parts.1);
-        }
+        let start = prod as usize % len;
+
+        /*
+         * Split the buffer in two parts, one that will be copied at
+         * the end of the ring buffer, another at the beginning.
+         *
+         * [(parts.1)C    P(parts.0)]
+         */
+        let parts = buffer.split_at(buffer.len().min(len - start));
+        self.ring
+            .index(start..start + parts.0.len())
+            .copy_from_slice(parts.0);
+        self.ring.index(..parts.1.len()).copy_from_slice(parts.1);
 
         self.prod
             .compare_exchange(
-                prod as u32,
-                dest_prod as u32,
+                prod,
+                prod.wrapping_add(buffer.len() as u32),
                 Ordering::Release,
                 Ordering::Relaxed,
             )
@@ -82,4 +95,31 @@ impl XenRing<'_> {
 
         Ok(())
     }
+
+    /// Read up to `buffer.len()` bytes as consumer, returns the number of
+    /// bytes read.
+    pub fn read(&mut self, buffer: &mut [u8]) -> Result<usize, XenRingError> {
+        let len = self.ring.len();
+        let (prod, cons) = self.indexes()?;
+
+        let count = buffer.len().min(prod.wrapping_sub(cons) as usize);
+        let start = cons as usize % len;
+
+        let (first, second) = buffer[..count].split_at_mut(count.min(len - start));
+        self.ring
+            .index(start..start + first.len())
+            .copy_into_slice(first);
+        self.ring.index(..second.len()).copy_into_slice(second);
+
+        self.cons
+            .compare_exchange(
+                cons,
+                cons.wrapping_add(count as u32),
+                Ordering::Release,
+                Ordering::Relaxed,
+            )
+            .map_err(|_| XenRingError::MisbehavingIndex)?;
+
+        Ok(count)
+    }
 }
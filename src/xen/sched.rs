@@ -0,0 +1,103 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! vCPU scheduling (`__HYPERVISOR_sched_op`)
+//!
+//! Lets the vCPU give its time back to Xen while waiting for an event,
+//! instead of spinning.
+
+use core::ptr;
+
+use crate::xen::{
+    XenError,
+    event::EventChannel,
+    hypercall::{call, hypercall2, xen_op},
+    shared_info,
+};
+
+const SCHED_OP: usize = 29;
+
+const SCHEDOP_YIELD: usize = 0;
+const SCHEDOP_BLOCK: usize = 1;
+const SCHEDOP_POLL: usize = 3;
+
+/// Maximum number of ports given to [`poll`].
+pub const SCHED_POLL_MAX_PORTS: usize = 128;
+
+xen_op! {
+    #[derive(Default)]
+    struct SchedPoll(SCHED_OP) {
+        ports: u64 => in(0),
+        nr_ports: u32 => in(1),
+        _pad: u32,
+        timeout: u64 => in(2),
+    }
+}
+
+/// Give the rest of our time slice to other vCPUs.
+pub fn yield_now() -> Result<(), XenError> {
+    unsafe { hypercall2(SCHED_OP, [SCHEDOP_YIELD, 0]) }?;
+
+    Ok(())
+}
+
+/// Block until an event is delivered to this vCPU.
+///
+/// Returns immediately if an upcall is already pending.
+pub fn block() -> Result<(), XenError> {
+    unsafe { hypercall2(SCHED_OP, [SCHEDOP_BLOCK, 0]) }?;
+
+    Ok(())
+}
+
+/// Block until one of `ports` is pending or Xen system time reaches
+/// `deadline` (see [`deadline_after`]).
+///
+/// Events are not consumed, and the call may return early.
+pub fn poll(ports: &[EventChannel], deadline: Option<u64>) -> Result<(), XenError> {
+    if ports.len() > SCHED_POLL_MAX_PORTS {
+        return Err(XenError::Inval);
+    }
+
+    let mut op = SchedPoll {
+        ports: ptr::from_ref(ports).addr() as u64,
+        nr_ports: ports.len() as u32,
+        timeout: deadline.unwrap_or(0),
+        ..Default::default()
+    };
+
+    unsafe { call(SCHEDOP_POLL, &mut op) }?;
+
+    Ok(())
+}
+
+/// Xen system time `timeout_ns` from now, usable as a deadline.
+///
+/// Returns None if the system time is unavailable (see
+/// [`shared_info::system_time`]).
+pub fn deadline_after(timeout_ns: u64) -> Option<u64> {
+    shared_info::system_time().map(|now| now + timeout_ns)
+}
+
+/// Wait for an event on `port` and consume it, blocking the vCPU meanwhile.
+///
+/// Returns false if `deadline` was given and no event came in time (or
+/// the wait ended early), callers are expected to check their condition
+/// again in any case. Without a deadline, this only returns once the event
+/// comes, so callers that can't rely on it (e.g. when [`deadline_after`]
+/// returns None) should rather [`yield_now`] and retry. The port shouldn't
+/// have a handler, which would consume the event first.
+pub fn wait_for_event(port: EventChannel, deadline: Option<u64>) -> Result<bool, XenError> {
+    loop {
+        if port.is_pending()? {
+            port.clear()?;
+            return Ok(true);
+        }
+
+        poll(&[port], deadline)?;
+
+        if !port.is_pending()? && deadline.is_some() {
+            return Ok(false);
+        }
+    }
+}
@@ -0,0 +1,201 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Shared info page (struct shared_info)
+//!
+//! Page shared with Xen holding the per-vCPU upcall state, the 2-level event
+//! channel bitmaps and the wallclock. All the fields may be updated by Xen at
+//! any time and are thus only exposed as atomics.
+
+use core::{
+    cell::SyncUnsafeCell,
+    ptr,
+    sync::atomic::{AtomicI8, AtomicPtr, AtomicU8, AtomicU32, AtomicU64, Ordering},
+};
+
+use crate::{
+    arch::map_4k_frame,
+    xen::{
+        XenError,
+        memory::{XenMapSpace, add_to_physmap},
+    },
+};
+
+/// Number of vcpu_info slots in the shared info page.
+#[cfg(target_arch = "x86_64")]
+pub const XEN_LEGACY_MAX_VCPUS: usize = 32;
+
+/// Number of vcpu_info slots in the shared info page.
+#[cfg(not(target_arch = "x86_64"))]
+pub const XEN_LEGACY_MAX_VCPUS: usize = 1;
+
+/// Number of bits in a xen_ulong_t.
+pub const BITS_PER_XEN_ULONG: usize = 64;
+
+/// struct vcpu_time_info
+#[repr(C)]
+pub struct VcpuTimeInfo {
+    /// Odd while Xen updates the structure.
+    pub version: AtomicU32,
+    _pad0: u32,
+    pub tsc_timestamp: AtomicU64,
+    pub system_time: AtomicU64,
+    pub tsc_to_system_mul: AtomicU32,
+    pub tsc_shift: AtomicI8,
+    pub flags: AtomicU8,
+    _pad1: [u8; 2],
+}
+
+#[cfg(target_arch = "x86_64")]
+impl VcpuTimeInfo {
+    /// Current Xen system time (ns since boot), scaled from the TSC.
+    pub fn system_time(&self) -> u64 {
+        loop {
+            let version = self.version.load(Ordering::Acquire);
+
+            // Odd while being updated.
+            if version & 1 != 0 {
+                continue;
+            }
+
+            let tsc_timestamp = self.tsc_timestamp.load(Ordering::Relaxed);
+            let system_time = self.system_time.load(Ordering::Relaxed);
+            let mul = self.tsc_to_system_mul.load(Ordering::Relaxed);
+            let shift = self.tsc_shift.load(Ordering::Relaxed);
+            let tsc = crate::delay::read_tsc();
+
+            if self.version.load(Ordering::Acquire) != version {
+                continue;
+            }
+
+            let mut delta = tsc.wrapping_sub(tsc_timestamp);
+            if shift < 0 {
+                delta >>= -shift;
+            } else {
+                delta <<= shift;
+            }
+
+            return system_time + ((delta as u128 * mul as u128) >> 32) as u64;
+        }
+    }
+}
+
+/// struct arch_vcpu_info
+#[cfg(target_arch = "x86_64")]
+#[repr(C)]
+pub struct ArchVcpuInfo {
+    pub cr2: AtomicU64,
+    _pad: u64,
+}
+
+/// struct arch_vcpu_info (empty on Arm and RISC-V)
+#[cfg(not(target_arch = "x86_64"))]
+#[repr(C)]
+pub struct ArchVcpuInfo {}
+
+/// struct vcpu_info
+#[repr(C)]
+pub struct VcpuInfo {
+    /// Set by Xen when an event is pending for this vCPU.
+    pub evtchn_upcall_pending: AtomicU8,
+    /// Upcalls are masked when non-zero (PV only).
+    pub evtchn_upcall_mask: AtomicU8,
+    /// 2-level ABI: one bit per word of `evtchn_pending` with pending events.
+    pub evtchn_pending_sel: AtomicU64,
+    pub arch: ArchVcpuInfo,
+    pub time: VcpuTimeInfo,
+}
+
+#[cfg(target_arch = "x86_64")]
+const _: () = assert!(size_of::<VcpuInfo>() == 64);
+
+#[cfg(not(target_arch = "x86_64"))]
+const _: () = assert!(size_of::<VcpuInfo>() == 48);
+
+/// struct shared_info, without the trailing arch_shared_info.
+#[repr(C)]
+pub struct SharedInfo {
+    pub vcpu_info: [VcpuInfo; XEN_LEGACY_MAX_VCPUS],
+    /// 2-level ABI pending bitmap, one bit per port.
+    pub evtchn_pending: [AtomicU64; BITS_PER_XEN_ULONG],
+    /// 2-level ABI mask bitmap, one bit per port.
+    pub evtchn_mask: [AtomicU64; BITS_PER_XEN_ULONG],
+    pub wc_version: AtomicU32,
+    pub wc_sec: AtomicU32,
+    pub wc_nsec: AtomicU32,
+    pub wc_sec_hi: AtomicU32,
+}
+
+impl SharedInfo {
+    /// vcpu_info of vCPU `id`, if it lives in the shared info page.
+    pub fn vcpu(&self, id: usize) -> Option<&VcpuInfo> {
+        self.vcpu_info.get(id)
+    }
+}
+
+/// Current Xen system time (ns since boot), as used for timeouts.
+///
+/// Only available on x86 once the shared info page is mapped, Xen doesn't
+/// maintain the time info on other architectures.
+pub fn system_time() -> Option<u64> {
+    #[cfg(target_arch = "x86_64")]
+    return get().map(|shared_info| shared_info.vcpu_info[0].time.system_time());
+
+    #[cfg(not(target_arch = "x86_64"))]
+    None
+}
+
+#[repr(C, align(4096))]
+struct SharedInfoPage([u8; 4096]);
+
+const _: () = assert!(size_of::<SharedInfo>() <= size_of::<SharedInfoPage>());
+
+/// Guest frame given to Xen, its original content is lost once mapped.
+static SHARED_INFO_PAGE: SyncUnsafeCell<SharedInfoPage> =
+    SyncUnsafeCell::new(SharedInfoPage([0; _]));
+
+static SHARED_INFO: AtomicPtr<SharedInfo> = AtomicPtr::new(ptr::null_mut());
+
+/// Shared info page, if already mapped with [`map`].
+pub fn get() -> Option<&'static SharedInfo> {
+    // SAFETY: Only ever set to the mapped shared info page.
+    unsafe { SHARED_INFO.load(Ordering::Acquire).as_ref() }
+}
+
+/// Map the shared info page (XENMAPSPACE_shared_info), does nothing if
+/// already mapped.
+pub fn map() -> Result<&'static SharedInfo, XenError> {
+    if let Some(shared_info) = get() {
+        return Ok(shared_info);
+    }
+
+    let pfn = (SHARED_INFO_PAGE.get().addr() >> 12) as u64;
+
+    // SAFETY: SHARED_INFO_PAGE is reserved for this purpose.
+    unsafe { add_to_physmap(XenMapSpace::SharedInfo, 0, pfn) }?;
+
+    // Xen doesn't know about memory encryption, access it as shared memory.
+    let shared_info = map_4k_frame::<SharedInfo>(pfn, false).ok_or(XenError::NoMem)?;
+
+    SHARED_INFO.store(shared_info.as_ptr(), Ordering::Release);
+
+    // SAFETY: The page is now owned by Xen and stays mapped.
+    Ok(unsafe { shared_info.as_ref() })
+}
+
+#[cfg(test)]
+mod tests {
+    use core::mem::offset_of;
+
+    use super::*;
+
+    #[test]
+    fn layout() {
+        let vcpus = XEN_LEGACY_MAX_VCPUS * size_of::<VcpuInfo>();
+
+        assert_eq!(offset_of!(VcpuInfo, evtchn_pending_sel), 8);
+        assert_eq!(offset_of!(SharedInfo, evtchn_pending), vcpus);
+        assert_eq!(offset_of!(SharedInfo, evtchn_mask), vcpus + 512);
+        assert_eq!(offset_of!(SharedInfo, wc_version), vcpus + 1024);
+    }
+}
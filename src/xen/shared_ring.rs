@@ -0,0 +1,661 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Request/response rings of the PV protocols (io/ring.h)
+//!
+//! A [`SharedRing`] (DEFINE_RING_TYPES sring) holds slots that are either a
+//! request or a response. The frontend queues requests and consumes responses
+//! through a [`FrontRing`], the backend does the opposite through a
+//! [`BackRing`]. Both keep private indexes, and only publish them with
+//! `push_*`, which tells whether the other end must be notified.
+//!
+//! All indexes are free-running, only masked when accessing the slots.
+
+use core::{
+    cell::UnsafeCell,
+    marker::PhantomData,
+    ptr::NonNull,
+    sync::atomic::{AtomicU32, Ordering, fence},
+};
+
+use super::ring::XenRingError;
+
+/// Size of the sring header, before the slots.
+const SRING_HEADER_SIZE: usize = 64;
+
+/// Header of the shared ring (struct __name##_sring)
+#[repr(C)]
+pub struct SharedRingHeader {
+    req_prod: AtomicU32,
+    req_event: AtomicU32,
+    rsp_prod: AtomicU32,
+    rsp_event: AtomicU32,
+    /// Not used by us, but the other end may still write it.
+    _pad: UnsafeCell<[u8; 48]>,
+}
+
+const _: () = assert!(size_of::<SharedRingHeader>() == SRING_HEADER_SIZE);
+
+/// Type of a ring slot, read from memory the other end controls.
+///
+/// # Safety
+///
+/// Implementors must be plain data for which every bit pattern is valid
+/// (e.g. no `bool`, `char`, enums or references).
+pub unsafe trait RingEntry: Copy {}
+
+macro_rules! ring_entry {
+    ($($ty:ty),*) => {
+        $(unsafe impl RingEntry for $ty {})*
+    };
+}
+
+ring_entry!(u8, u16, u32, u64, i8, i16, i32, i64);
+
+unsafe impl<T: RingEntry, const N: usize> RingEntry for [T; N] {}
+
+/// union __name##_sring_entry
+#[repr(C)]
+union SharedRingEntry<Req: RingEntry, Rsp: RingEntry> {
+    req: Req,
+    rsp: Rsp,
+}
+
+/// Number of slots of a `len` bytes shared ring (__RING_SIZE), rounded down to
+/// a power of two.
+pub const fn ring_size<Req: RingEntry, Rsp: RingEntry>(len: usize) -> u32 {
+    let entry = size_of::<SharedRingEntry<Req, Rsp>>();
+
+    if len <= SRING_HEADER_SIZE || entry == 0 {
+        return 0;
+    }
+
+    match (len - SRING_HEADER_SIZE) / entry {
+        0 => 0,
+        slots => 1 << slots.ilog2(),
+    }
+}
+
+/// Shared ring in memory shared with the other end.
+pub struct SharedRing<'a, Req: RingEntry, Rsp: RingEntry> {
+    header: &'a SharedRingHeader,
+    entries: NonNull<SharedRingEntry<Req, Rsp>>,
+    size: u32,
+    _marker: PhantomData<&'a SharedRingEntry<Req, Rsp>>,
+}
+
+unsafe impl<Req: RingEntry + Send, Rsp: RingEntry + Send> Send for SharedRing<'_, Req, Rsp> {}
+
+impl<'a, Req: RingEntry, Rsp: RingEntry> SharedRing<'a, Req, Rsp> {
+    /// Use the `len` bytes at `ptr` as a shared ring.
+    ///
+    /// Returns None if the ring can't hold a single slot.
+    ///
+    /// # Safety
+    ///
+    /// `ptr` must be valid for `len` bytes during `'a`, and suitably aligned
+    /// (page aligned in practice). The other end may only access it through
+    /// the ring protocol.
+    pub unsafe fn new(ptr: NonNull<u8>, len: usize) -> Option<Self> {
+        let size = ring_size::<Req, Rsp>(len);
+
+        if size == 0 {
+            return None;
+        }
+
+        Some(Self {
+            header: unsafe { ptr.cast().as_ref() },
+            entries: unsafe { ptr.add(SRING_HEADER_SIZE).cast() },
+            size,
+            _marker: PhantomData,
+        })
+    }
+
+    /// Reset the indexes (SHARED_RING_INIT), before sharing the ring.
+    pub fn init(&mut self) {
+        self.header.req_prod.store(0, Ordering::Relaxed);
+        self.header.rsp_prod.store(0, Ordering::Relaxed);
+        self.header.req_event.store(1, Ordering::Relaxed);
+        self.header.rsp_event.store(1, Ordering::Relaxed);
+    }
+
+    /// Number of slots.
+    pub fn size(&self) -> u32 {
+        self.size
+    }
+
+    fn entry(&self, idx: u32) -> *mut SharedRingEntry<Req, Rsp> {
+        // SAFETY: Masked within the ring.
+        unsafe { self.entries.as_ptr().add((idx & (self.size - 1)) as usize) }
+    }
+
+    fn read_req(&self, idx: u32) -> Req {
+        // Copy it out, the other end may still change it (RING_COPY_REQUEST).
+        unsafe { (&raw const (*self.entry(idx)).req).read_volatile() }
+    }
+
+    fn write_req(&self, idx: u32, req: Req) {
+        unsafe { (&raw mut (*self.entry(idx)).req).write_volatile(req) }
+    }
+
+    fn read_rsp(&self, idx: u32) -> Rsp {
+        unsafe { (&raw const (*self.entry(idx)).rsp).read_volatile() }
+    }
+
+    fn write_rsp(&self, idx: u32, rsp: Rsp) {
+        unsafe { (&raw mut (*self.entry(idx)).rsp).write_volatile(rsp) }
+    }
+}
+
+/// Publish `new` in `prod`, returns whether the other end asked to be
+/// notified by setting `event` within the pushed range.
+fn push_and_check_notify(prod: &AtomicU32, event: &AtomicU32, new: u32) -> bool {
+    let old = prod.load(Ordering::Relaxed);
+
+    // Slots are written before the index (wmb).
+    prod.store(new, Ordering::Release);
+    // The index is visible before looking at the event (mb).
+    fence(Ordering::SeqCst);
+
+    new.wrapping_sub(event.load(Ordering::Relaxed)) < new.wrapping_sub(old)
+}
+
+/// Frontend view of the ring (struct __name##_front_ring)
+pub struct FrontRing<'a, Req: RingEntry, Rsp: RingEntry> {
+    req_prod_pvt: u32,
+    rsp_cons: u32,
+    sring: SharedRing<'a, Req, Rsp>,
+}
+
+impl<'a, Req: RingEntry, Rsp: RingEntry> FrontRing<'a, Req, Rsp> {
+    /// Initialize `sring` and take it (SHARED_RING_INIT, FRONT_RING_INIT).
+    pub fn new(mut sring: SharedRing<'a, Req, Rsp>) -> Self {
+        sring.init();
+
+        Self {
+            req_prod_pvt: 0,
+            rsp_cons: 0,
+            sring,
+        }
+    }
+
+    /// Take a ring already in use (FRONT_RING_ATTACH).
+    pub fn attach(sring: SharedRing<'a, Req, Rsp>) -> Self {
+        let req_prod = sring.header.req_prod.load(Ordering::Acquire);
+
+        Self {
+            req_prod_pvt: req_prod,
+            rsp_cons: sring.header.rsp_prod.load(Ordering::Acquire),
+            sring,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.sring.size
+    }
+
+    /// Number of slots that can take a request (RING_FREE_REQUESTS).
+    pub fn free_requests(&self) -> u32 {
+        self.sring.size - self.req_prod_pvt.wrapping_sub(self.rsp_cons)
+    }
+
+    /// RING_FULL
+    pub fn is_full(&self) -> bool {
+        self.free_requests() == 0
+    }
+
+    /// Queue `req`, it is only visible to the backend after
+    /// [`Self::push_requests`].
+    pub fn queue_request(&mut self, req: Req) -> Result<(), XenRingError> {
+        if self.is_full() {
+            return Err(XenRingError::NotReady);
+        }
+
+        self.sring.write_req(self.req_prod_pvt, req);
+        self.req_prod_pvt = self.req_prod_pvt.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Publish the queued requests, returns whether the backend must be
+    /// notified (RING_PUSH_REQUESTS_AND_CHECK_NOTIFY).
+    pub fn push_requests(&mut self) -> bool {
+        let header = self.sring.header;
+
+        push_and_check_notify(&header.req_prod, &header.req_event, self.req_prod_pvt)
+    }
+
+    /// Number of responses to consume (RING_HAS_UNCONSUMED_RESPONSES).
+    pub fn unconsumed_responses(&self) -> Result<u32, XenRingError> {
+        let rsp_prod = self.sring.header.rsp_prod.load(Ordering::Acquire);
+        let unconsumed = rsp_prod.wrapping_sub(self.rsp_cons);
+
+        // The backend can only answer the requests we pushed.
+        if unconsumed > self.req_prod_pvt.wrapping_sub(self.rsp_cons) {
+            return Err(XenRingError::MisbehavingIndex);
+        }
+
+        Ok(unconsumed)
+    }
+
+    /// Consume the next response, if any.
+    pub fn take_response(&mut self) -> Result<Option<Rsp>, XenRingError> {
+        if self.unconsumed_responses()? == 0 {
+            return Ok(None);
+        }
+
+        let rsp = self.sring.read_rsp(self.rsp_cons);
+        self.rsp_cons = self.rsp_cons.wrapping_add(1);
+
+        Ok(Some(rsp))
+    }
+
+    /// Check for responses, asking for a notification on the next one if there
+    /// are none (RING_FINAL_CHECK_FOR_RESPONSES).
+    ///
+    /// Returns whether there are responses to consume.
+    pub fn final_check_for_responses(&mut self) -> Result<bool, XenRingError> {
+        if self.unconsumed_responses()? != 0 {
+            return Ok(true);
+        }
+
+        self.sring
+            .header
+            .rsp_event
+            .store(self.rsp_cons.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+
+        Ok(self.unconsumed_responses()? != 0)
+    }
+}
+
+/// Backend view of the ring (struct __name##_back_ring)
+pub struct BackRing<'a, Req: RingEntry, Rsp: RingEntry> {
+    rsp_prod_pvt: u32,
+    req_cons: u32,
+    sring: SharedRing<'a, Req, Rsp>,
+}
+
+impl<'a, Req: RingEntry, Rsp: RingEntry> BackRing<'a, Req, Rsp> {
+    /// Take a ring initialized by the frontend (BACK_RING_INIT).
+    pub fn new(sring: SharedRing<'a, Req, Rsp>) -> Self {
+        Self {
+            rsp_prod_pvt: 0,
+            req_cons: 0,
+            sring,
+        }
+    }
+
+    /// Take a ring already in use (BACK_RING_ATTACH).
+    pub fn attach(sring: SharedRing<'a, Req, Rsp>) -> Self {
+        let rsp_prod = sring.header.rsp_prod.load(Ordering::Acquire);
+
+        Self {
+            rsp_prod_pvt: rsp_prod,
+            req_cons: rsp_prod,
+            sring,
+        }
+    }
+
+    pub fn size(&self) -> u32 {
+        self.sring.size
+    }
+
+    /// Number of requests to consume (RING_HAS_UNCONSUMED_REQUESTS).
+    ///
+    /// Bounded by the free response slots, so that a frontend moving
+    /// req_prod too far can't make us overwrite pending responses.
+    pub fn unconsumed_requests(&self) -> u32 {
+        let req_prod = self.sring.header.req_prod.load(Ordering::Acquire);
+        let req = req_prod.wrapping_sub(self.req_cons);
+        let rsp = self.sring.size - self.req_cons.wrapping_sub(self.rsp_prod_pvt);
+
+        req.min(rsp)
+    }
+
+    /// Consume the next request, if any.
+    pub fn take_request(&mut self) -> Option<Req> {
+        if self.unconsumed_requests() == 0 {
+            return None;
+        }
+
+        let req = self.sring.read_req(self.req_cons);
+        self.req_cons = self.req_cons.wrapping_add(1);
+
+        Some(req)
+    }
+
+    /// Queue `rsp` for a consumed request, it is only visible to the frontend
+    /// after [`Self::push_responses`].
+    pub fn queue_response(&mut self, rsp: Rsp) -> Result<(), XenRingError> {
+        if self.rsp_prod_pvt == self.req_cons {
+            return Err(XenRingError::NotReady);
+        }
+
+        self.sring.write_rsp(self.rsp_prod_pvt, rsp);
+        self.rsp_prod_pvt = self.rsp_prod_pvt.wrapping_add(1);
+
+        Ok(())
+    }
+
+    /// Publish the queued responses, returns whether the frontend must be
+    /// notified (RING_PUSH_RESPONSES_AND_CHECK_NOTIFY).
+    pub fn push_responses(&mut self) -> bool {
+        let header = self.sring.header;
+
+        push_and_check_notify(&header.rsp_prod, &header.rsp_event, self.rsp_prod_pvt)
+    }
+
+    /// Check for requests, asking for a notification on the next one if there
+    /// are none (RING_FINAL_CHECK_FOR_REQUESTS).
+    ///
+    /// Returns whether there are requests to consume.
+    pub fn final_check_for_requests(&mut self) -> bool {
+        if self.unconsumed_requests() != 0 {
+            return true;
+        }
+
+        self.sring
+            .header
+            .req_event
+            .store(self.req_cons.wrapping_add(1), Ordering::Relaxed);
+        fence(Ordering::SeqCst);
+
+        self.unconsumed_requests() != 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[repr(C, align(4096))]
+    struct Page([u8; 4096]);
+
+    /// Small xorshift generator, for reproducible sequences.
+    struct Rng(u64);
+
+    impl Rng {
+        fn below(&mut self, n: u32) -> u32 {
+            self.0 ^= self.0 << 13;
+            self.0 ^= self.0 >> 7;
+            self.0 ^= self.0 << 17;
+
+            (self.0 % n as u64) as u32
+        }
+    }
+
+    /// Private indexes of both ends, updated as the io/ring.h macros do.
+    #[derive(Default)]
+    struct Model {
+        size: u32,
+        req_prod_pvt: u32,
+        rsp_cons: u32,
+        rsp_prod_pvt: u32,
+        req_cons: u32,
+    }
+
+    impl Model {
+        /// RING_PUSH_*_AND_CHECK_NOTIFY
+        fn push(prod: &AtomicU32, event: &AtomicU32, new: u32) -> bool {
+            let old = prod.load(Ordering::Relaxed);
+            prod.store(new, Ordering::Relaxed);
+
+            new.wrapping_sub(event.load(Ordering::Relaxed)) < new.wrapping_sub(old)
+        }
+
+        /// RING_FREE_REQUESTS
+        fn free_requests(&self) -> u32 {
+            self.size - (self.req_prod_pvt.wrapping_sub(self.rsp_cons))
+        }
+
+        /// RING_HAS_UNCONSUMED_RESPONSES
+        fn unconsumed_responses(&self, header: &SharedRingHeader) -> u32 {
+            header
+                .rsp_prod
+                .load(Ordering::Relaxed)
+                .wrapping_sub(self.rsp_cons)
+        }
+
+        /// RING_HAS_UNCONSUMED_REQUESTS
+        fn unconsumed_requests(&self, header: &SharedRingHeader) -> u32 {
+            let req = header
+                .req_prod
+                .load(Ordering::Relaxed)
+                .wrapping_sub(self.req_cons);
+            let rsp = self.size - self.req_cons.wrapping_sub(self.rsp_prod_pvt);
+
+            req.min(rsp)
+        }
+    }
+
+    /// Page for a ring, kept for the rest of the test run.
+    fn page() -> NonNull<u8> {
+        NonNull::from(Box::leak(Box::new(Page([0xff; 4096])))).cast()
+    }
+
+    /// View of the ring of `u64` requests and responses at `page`.
+    fn view(page: NonNull<u8>) -> SharedRing<'static, u64, u64> {
+        unsafe { SharedRing::new(page, size_of::<Page>()) }.unwrap()
+    }
+
+    /// Ring at `page`, with all the indexes starting at `start`.
+    fn ring(page: NonNull<u8>, start: u32) -> SharedRing<'static, u64, u64> {
+        let ring = view(page);
+
+        ring.header.req_prod.store(start, Ordering::Relaxed);
+        ring.header.rsp_prod.store(start, Ordering::Relaxed);
+        ring.header
+            .req_event
+            .store(start.wrapping_add(1), Ordering::Relaxed);
+        ring.header
+            .rsp_event
+            .store(start.wrapping_add(1), Ordering::Relaxed);
+
+        ring
+    }
+
+    #[test]
+    fn size() {
+        // 4032 bytes of slots, rounded down to a power of two.
+        assert_eq!(ring_size::<u64, u64>(4096), 256);
+        assert_eq!(ring_size::<[u8; 112], u8>(4096), 32);
+        assert_eq!(ring_size::<u64, u64>(64), 0);
+        assert_eq!(ring_size::<u64, u64>(72), 1);
+    }
+
+    #[test]
+    fn init() {
+        let front = FrontRing::new(ring(page(), 42));
+        let header = front.sring.header;
+
+        assert_eq!(header.req_prod.load(Ordering::Relaxed), 0);
+        assert_eq!(header.rsp_prod.load(Ordering::Relaxed), 0);
+        assert_eq!(header.req_event.load(Ordering::Relaxed), 1);
+        assert_eq!(header.rsp_event.load(Ordering::Relaxed), 1);
+        assert_eq!(front.free_requests(), 256);
+    }
+
+    /// Run random operations on both ends, checking them against the model.
+    fn check_against_model(start: u32, seed: u64) {
+        let page = page();
+
+        // Both ends share the page, as the frontend and backend would.
+        let mut front = FrontRing::attach(ring(page, start));
+        let mut back = BackRing::attach(view(page));
+        let header = front.sring.header;
+
+        let mut model = Model {
+            size: front.size(),
+            req_prod_pvt: start,
+            rsp_cons: start,
+            rsp_prod_pvt: start,
+            req_cons: start,
+        };
+        let mut rng = Rng(seed);
+        let (mut next_req, mut next_rsp) = (0u64, 0u64);
+        // Whether an end went to sleep after its final check.
+        let (mut front_waits, mut back_waits) = (false, false);
+
+        for _ in 0..20_000 {
+            assert_eq!(front.free_requests(), model.free_requests());
+            assert_eq!(
+                front.unconsumed_responses(),
+                Ok(model.unconsumed_responses(header))
+            );
+            assert_eq!(
+                back.unconsumed_requests(),
+                model.unconsumed_requests(header)
+            );
+
+            match rng.below(6) {
+                // Frontend queues and pushes requests.
+                0 => {
+                    let count = rng.below(model.free_requests() + 1);
+
+                    for _ in 0..count {
+                        front.queue_request(next_req).unwrap();
+                        next_req += 1;
+                    }
+                    model.req_prod_pvt = model.req_prod_pvt.wrapping_add(count);
+
+                    if model.free_requests() == 0 {
+                        assert!(front.is_full());
+                        assert_eq!(front.queue_request(0), Err(XenRingError::NotReady));
+                    }
+
+                    let expected = {
+                        // Replay the push on a copy of the header.
+                        let prod = AtomicU32::new(header.req_prod.load(Ordering::Relaxed));
+                        Model::push(&prod, &header.req_event, model.req_prod_pvt)
+                    };
+                    let notify = front.push_requests();
+
+                    assert_eq!(notify, expected);
+                    if back_waits && count != 0 {
+                        assert!(notify, "lost request notification");
+                        back_waits = false;
+                    }
+                }
+                // Backend consumes requests, answering them.
+                1 => {
+                    let count = rng.below(model.unconsumed_requests(header) + 1);
+
+                    for _ in 0..count {
+                        let req = back.take_request().unwrap();
+                        assert_eq!(req, next_rsp);
+
+                        back.queue_response(req).unwrap();
+                        next_rsp += 1;
+                    }
+                    model.req_cons = model.req_cons.wrapping_add(count);
+                    model.rsp_prod_pvt = model.rsp_prod_pvt.wrapping_add(count);
+                }
+                // Backend pushes the responses.
+                2 => {
+                    let expected = {
+                        let prod = AtomicU32::new(header.rsp_prod.load(Ordering::Relaxed));
+                        Model::push(&prod, &header.rsp_event, model.rsp_prod_pvt)
+                    };
+                    let pushed = model.rsp_prod_pvt != header.rsp_prod.load(Ordering::Relaxed);
+                    let notify = back.push_responses();
+
+                    assert_eq!(notify, expected);
+                    if front_waits && pushed {
+                        assert!(notify, "lost response notification");
+                        front_waits = false;
+                    }
+                }
+                // Frontend consumes responses.
+                3 => {
+                    let count = rng.below(model.unconsumed_responses(header) + 1);
+
+                    for _ in 0..count {
+                        front.take_response().unwrap().unwrap();
+                    }
+                    model.rsp_cons = model.rsp_cons.wrapping_add(count);
+                }
+                // Frontend checks for responses before sleeping.
+                4 => {
+                    let mut expected = model.unconsumed_responses(header) != 0;
+                    let mut rsp_event = header.rsp_event.load(Ordering::Relaxed);
+
+                    if !expected {
+                        rsp_event = model.rsp_cons.wrapping_add(1);
+                        expected = model.unconsumed_responses(header) != 0;
+                    }
+
+                    assert_eq!(front.final_check_for_responses(), Ok(expected));
+                    assert_eq!(header.rsp_event.load(Ordering::Relaxed), rsp_event);
+                    front_waits = !expected;
+                }
+                // Backend checks for requests before sleeping.
+                _ => {
+                    let mut expected = model.unconsumed_requests(header) != 0;
+                    let mut req_event = header.req_event.load(Ordering::Relaxed);
+
+                    if !expected {
+                        req_event = model.req_cons.wrapping_add(1);
+                        expected = model.unconsumed_requests(header) != 0;
+                    }
+
+                    assert_eq!(back.final_check_for_requests(), expected);
+                    assert_eq!(header.req_event.load(Ordering::Relaxed), req_event);
+                    back_waits = !expected;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn model() {
+        for seed in 1..=8 {
+            check_against_model(0, seed);
+        }
+    }
+
+    #[test]
+    fn model_wraparound() {
+        for (start, seed) in [(u32::MAX, 1), (u32::MAX - 100, 2), (u32::MAX - 255, 3)] {
+            check_against_model(start, seed);
+        }
+    }
+
+    #[test]
+    fn misbehaving_backend() {
+        let mut front = FrontRing::attach(ring(page(), u32::MAX - 1));
+
+        front.queue_request(1).unwrap();
+        front.push_requests();
+
+        // More responses than requests.
+        let header = front.sring.header;
+        header.rsp_prod.store(1, Ordering::Relaxed);
+        assert_eq!(
+            front.unconsumed_responses(),
+            Err(XenRingError::MisbehavingIndex)
+        );
+
+        header.rsp_prod.store(u32::MAX, Ordering::Relaxed);
+        assert_eq!(front.unconsumed_responses(), Ok(1));
+    }
+
+    #[test]
+    fn misbehaving_frontend() {
+        let mut back = BackRing::attach(ring(page(), u32::MAX - 1));
+
+        // req_prod moved past a whole ring.
+        let header = back.sring.header;
+        header.req_prod.store(1000, Ordering::Relaxed);
+        assert_eq!(back.unconsumed_requests(), 256);
+
+        for _ in 0..256 {
+            back.take_request().unwrap();
+        }
+
+        // No room left for the responses.
+        assert_eq!(back.take_request(), None);
+        back.queue_response(0).unwrap();
+        assert_eq!(back.unconsumed_requests(), 1);
+    }
+}
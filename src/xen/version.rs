@@ -0,0 +1,248 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Hypervisor version queries (`__HYPERVISOR_xen_version`)
+
+use core::{fmt, ptr};
+
+use crate::xen::{XenError, hypercall::hypercall2};
+
+const XEN_VERSION: usize = 17;
+
+const XENVER_VERSION: usize = 0;
+const XENVER_EXTRAVERSION: usize = 1;
+const XENVER_COMPILE_INFO: usize = 2;
+const XENVER_CAPABILITIES: usize = 3;
+const XENVER_CHANGESET: usize = 4;
+const XENVER_PLATFORM_PARAMETERS: usize = 5;
+const XENVER_GET_FEATURES: usize = 6;
+const XENVER_BUILD_ID: usize = 10;
+
+/// Maximum build id size we retrieve.
+const BUILD_ID_MAX: usize = 64;
+
+bitflags::bitflags! {
+    /// XENFEAT_* flags (first submap of XENVER_get_features)
+    #[derive(Clone, Copy, Debug, Default)]
+    pub struct XenFeatures: u32 {
+        const WRITABLE_PAGE_TABLES = 1 << 0;
+        const WRITABLE_DESCRIPTOR_TABLES = 1 << 1;
+        const AUTO_TRANSLATED_PHYSMAP = 1 << 2;
+        const SUPERVISOR_MODE_KERNEL = 1 << 3;
+        const PAE_PGDIR_ABOVE_4GB = 1 << 4;
+        const MMU_PT_UPDATE_PRESERVE_AD = 1 << 5;
+        const HIGHMEM_ASSIST = 1 << 6;
+        const GNTTAB_MAP_AVAIL_BITS = 1 << 7;
+        const HVM_CALLBACK_VECTOR = 1 << 8;
+        const HVM_SAFE_PVCLOCK = 1 << 9;
+        const HVM_PIRQS = 1 << 10;
+        const DOM0 = 1 << 11;
+        const GRANT_MAP_IDENTITY = 1 << 12;
+        const MEMORY_OP_VNODE_SUPPORTED = 1 << 13;
+        const ARM_SMCCC_SUPPORTED = 1 << 14;
+        const LINUX_RSDP_UNRESTRICTED = 1 << 15;
+        const NOT_DIRECT_MAPPED = 1 << 16;
+        const DIRECT_MAPPED = 1 << 17;
+    }
+}
+
+/// Fixed size, NUL terminated string returned by Xen.
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+pub struct XenString<const N: usize>([u8; N]);
+
+impl<const N: usize> XenString<N> {
+    const fn new() -> Self {
+        Self([0; N])
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        let len = self.0.iter().position(|&c| c == 0).unwrap_or(N);
+        &self.0[..len]
+    }
+
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(self.as_bytes()).unwrap_or("(non-utf8)")
+    }
+}
+
+impl<const N: usize> fmt::Display for XenString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl<const N: usize> fmt::Debug for XenString<N> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Build informations (struct xen_compile_info).
+#[derive(Clone, Copy, Debug)]
+#[repr(C)]
+pub struct CompileInfo {
+    pub compiler: XenString<64>,
+    pub compile_by: XenString<16>,
+    pub compile_domain: XenString<32>,
+    pub compile_date: XenString<32>,
+}
+
+/// Build id of the hypervisor binary.
+#[derive(Clone, Copy)]
+pub struct BuildId {
+    len: usize,
+    buf: [u8; BUILD_ID_MAX],
+}
+
+impl BuildId {
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl fmt::Display for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.as_bytes()
+            .iter()
+            .try_for_each(|b| write!(f, "{b:02x}"))
+    }
+}
+
+impl fmt::Debug for BuildId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "BuildId({self})")
+    }
+}
+
+unsafe fn xen_version<T>(cmd: usize, arg: *mut T) -> Result<usize, XenError> {
+    unsafe { hypercall2(XEN_VERSION, [cmd, arg.addr()]) }
+}
+
+unsafe fn query_string<const N: usize>(cmd: usize) -> Result<XenString<N>, XenError> {
+    let mut s = XenString::new();
+
+    unsafe { xen_version(cmd, ptr::from_mut(&mut s)) }?;
+
+    Ok(s)
+}
+
+/// Hypervisor version as (major, minor).
+pub fn version() -> Result<(u16, u16), XenError> {
+    let version = unsafe { xen_version::<()>(XENVER_VERSION, ptr::null_mut()) }?;
+
+    Ok(((version >> 16) as u16, version as u16))
+}
+
+/// Version suffix (e.g. ".2" or "-rc").
+pub fn extraversion() -> Result<XenString<16>, XenError> {
+    unsafe { query_string(XENVER_EXTRAVERSION) }
+}
+
+pub fn compile_info() -> Result<CompileInfo, XenError> {
+    let mut info = CompileInfo {
+        compiler: XenString::new(),
+        compile_by: XenString::new(),
+        compile_domain: XenString::new(),
+        compile_date: XenString::new(),
+    };
+
+    unsafe { xen_version(XENVER_COMPILE_INFO, ptr::from_mut(&mut info)) }?;
+
+    Ok(info)
+}
+
+/// Supported guest types (e.g. "xen-3.0-x86_64 hvm-3.0-x86_64").
+pub fn capabilities() -> Result<XenString<1024>, XenError> {
+    unsafe { query_string(XENVER_CAPABILITIES) }
+}
+
+/// Source control revision the hypervisor was built from.
+pub fn changeset() -> Result<XenString<64>, XenError> {
+    unsafe { query_string(XENVER_CHANGESET) }
+}
+
+/// Start of the hypervisor virtual address range (xen_platform_parameters).
+pub fn platform_parameters() -> Result<u64, XenError> {
+    let mut virt_start = 0u64;
+
+    unsafe { xen_version(XENVER_PLATFORM_PARAMETERS, ptr::from_mut(&mut virt_start)) }?;
+
+    Ok(virt_start)
+}
+
+pub fn get_features() -> Result<XenFeatures, XenError> {
+    #[repr(C)]
+    struct XenFeatureInfo {
+        submap_idx: u32,
+        submap: u32,
+    }
+
+    let mut info = XenFeatureInfo {
+        submap_idx: 0,
+        submap: 0,
+    };
+
+    unsafe { xen_version(XENVER_GET_FEATURES, ptr::from_mut(&mut info)) }?;
+
+    Ok(XenFeatures::from_bits_retain(info.submap))
+}
+
+pub fn build_id() -> Result<BuildId, XenError> {
+    #[repr(C)]
+    struct XenBuildId {
+        len: u32,
+        buf: [u8; BUILD_ID_MAX],
+    }
+
+    let mut id = XenBuildId {
+        len: BUILD_ID_MAX as u32,
+        buf: [0; _],
+    };
+
+    let len = unsafe { xen_version(XENVER_BUILD_ID, ptr::from_mut(&mut id)) }?;
+
+    Ok(BuildId {
+        len: len.min(BUILD_ID_MAX),
+        buf: id.buf,
+    })
+}
+
+/// Summary of all the XENVER_* queries.
+#[derive(Clone, Copy, Debug)]
+pub struct XenVersion {
+    pub major: u16,
+    pub minor: u16,
+    pub extraversion: XenString<16>,
+    pub compile_info: CompileInfo,
+    pub capabilities: XenString<1024>,
+    pub changeset: XenString<64>,
+    pub virt_start: u64,
+    pub features: XenFeatures,
+    /// Missing when the build id is unavailable or denied by XSM.
+    pub build_id: Option<BuildId>,
+}
+
+impl XenVersion {
+    pub fn query() -> Result<Self, XenError> {
+        let (major, minor) = version()?;
+
+        Ok(Self {
+            major,
+            minor,
+            extraversion: extraversion()?,
+            compile_info: compile_info()?,
+            capabilities: capabilities()?,
+            changeset: changeset()?,
+            virt_start: platform_parameters()?,
+            features: get_features()?,
+            build_id: build_id().ok(),
+        })
+    }
+}
+
+impl fmt::Display for XenVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Xen {}.{}{}", self.major, self.minor, self.extraversion)
+    }
+}
@@ -0,0 +1,657 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenBus backends
+//!
+//! [`BackendDevice`] drives the backend side of the handshake, calling the
+//! [`Backend`] driver as the frontend state changes:
+//!
+//! | frontend                     | driver       | backend      |
+//! |------------------------------|--------------|--------------|
+//! | (any)                        | `probe`      | InitWait     |
+//! | Initialised/Connected        | `connect`    | Connected    |
+//! | Closing                      | `disconnect` | Closing      |
+//! | Closed/Unknown (gone)        | `disconnect` | Closed       |
+//! | Initialising, once Closed    | `probe`      | InitWait     |
+//!
+//! Before `connect`, the `ring-ref` grant published by the frontend is mapped
+//! and its `event-channel` bound, see [`BackendDevice::ring`]. This goes
+//! through [`BackendResources`], so that the handshake can be driven without
+//! Xen.
+
+use crate::xen::{
+    DomId, XenError,
+    event::OwnedEventChannel,
+    grant::{GrantRef, map::GrantMapping},
+    xenstore::{XenStore, XenStoreAccess, XenStoreTransport, XsStrings},
+};
+
+use super::{XenbusPath, XenbusState, read_state, read_u32, state_changed, write_state};
+
+/// PV driver plugged into [`BackendDevice`].
+pub trait Backend<R: BackendResources = XenResources> {
+    /// Device type, as in `backend/<type>/<frontend domid>/<id>`.
+    const DEVICE_TYPE: &'static str;
+
+    /// New device: publish the backend features in the backend node.
+    fn probe<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &BackendDevice<Self, R>,
+    ) -> Result<(), XenError>
+    where
+        Self: Sized;
+
+    /// The frontend published its ring, which is now mapped.
+    fn connect<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &BackendDevice<Self, R>,
+    ) -> Result<(), XenError>
+    where
+        Self: Sized;
+
+    /// The frontend is going away, stop using the ring (unmapped afterward).
+    fn disconnect<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &BackendDevice<Self, R>,
+    ) where
+        Self: Sized;
+}
+
+/// Access to the resources shared by the frontend, as [`XenStoreAccess`]
+/// is to its nodes.
+pub trait BackendResources {
+    /// Mapped grant, unmapped on drop.
+    type Mapping;
+    /// Bound event channel, closed on drop.
+    type EventChannel;
+
+    /// Map grant `gref` of `domid`.
+    fn map_grant(
+        &mut self,
+        domid: DomId,
+        gref: GrantRef,
+        readonly: bool,
+    ) -> Result<Self::Mapping, XenError>;
+
+    /// Bind our end of port `remote_port` of `domid`.
+    fn bind_interdomain(
+        &mut self,
+        domid: DomId,
+        remote_port: u32,
+    ) -> Result<Self::EventChannel, XenError>;
+}
+
+/// Grant table and event channel hypercalls
+#[derive(Clone, Copy, Debug, Default)]
+pub struct XenResources;
+
+impl BackendResources for XenResources {
+    type Mapping = GrantMapping;
+    type EventChannel = OwnedEventChannel;
+
+    fn map_grant(
+        &mut self,
+        domid: DomId,
+        gref: GrantRef,
+        readonly: bool,
+    ) -> Result<GrantMapping, XenError> {
+        GrantMapping::map(domid, gref, readonly)
+    }
+
+    fn bind_interdomain(
+        &mut self,
+        domid: DomId,
+        remote_port: u32,
+    ) -> Result<OwnedEventChannel, XenError> {
+        OwnedEventChannel::bind_interdomain(domid, remote_port)
+    }
+}
+
+/// Ring shared by the frontend
+pub struct BackendRing<R: BackendResources = XenResources> {
+    /// Mapping of `ring-ref`
+    pub ring: R::Mapping,
+    /// Bound `event-channel`
+    pub event_channel: R::EventChannel,
+}
+
+/// Watch `backend/<type>` to be notified of new (or removed) devices.
+///
+/// Events are queued until [`XenStore::dispatch_watches`], the devices are
+/// then listed with [`enumerate_frontends`] and [`enumerate`].
+pub fn watch_devices<B: Backend, T: XenStoreTransport>(
+    xs: &mut XenStore<T>,
+) -> Result<(), XenError> {
+    let path = XenbusPath::new(format_args!("backend/{}", B::DEVICE_TYPE))?;
+    let token = XenbusPath::new(format_args!("be:{}", B::DEVICE_TYPE))?;
+
+    xs.watch(path.as_str(), token.as_str(), state_changed)
+}
+
+/// Domains having `B::DEVICE_TYPE` devices, using `buf` for storage.
+pub fn enumerate_frontends<'b, B: Backend>(
+    xs: &mut impl XenStoreAccess,
+    buf: &'b mut [u8],
+) -> Result<XsStrings<'b>, XenError> {
+    let path = XenbusPath::new(format_args!("backend/{}", B::DEVICE_TYPE))?;
+
+    xs.directory(path.as_str(), buf)
+}
+
+/// Identifiers of the `B::DEVICE_TYPE` devices of `frontend_id`, using `buf`
+/// for storage.
+pub fn enumerate<'b, B: Backend>(
+    xs: &mut impl XenStoreAccess,
+    frontend_id: DomId,
+    buf: &'b mut [u8],
+) -> Result<XsStrings<'b>, XenError> {
+    let path = XenbusPath::new(format_args!("backend/{}/{}", B::DEVICE_TYPE, frontend_id.0))?;
+
+    xs.directory(path.as_str(), buf)
+}
+
+/// Backend side of a device
+pub struct BackendDevice<B: Backend<R>, R: BackendResources = XenResources> {
+    /// Backend node (`backend/<type>/<frontend domid>/<id>`)
+    pub nodename: XenbusPath,
+    /// Frontend node
+    pub frontend: XenbusPath,
+    pub frontend_id: DomId,
+    state: XenbusState,
+    /// Watch token, for the frontend state.
+    token: XenbusPath,
+    ring: Option<BackendRing<R>>,
+    resources: R,
+    driver: Option<B>,
+}
+
+impl<B: Backend> BackendDevice<B> {
+    /// Take device `id` of `frontend_id` with `driver`, and start watching
+    /// its frontend.
+    ///
+    /// The driver is called by [`Self::update`].
+    pub fn new<T: XenStoreTransport>(
+        xs: &mut XenStore<T>,
+        frontend_id: DomId,
+        id: &str,
+        driver: B,
+    ) -> Result<Self, XenError> {
+        Self::with_resources(xs, frontend_id, id, driver, XenResources)
+    }
+}
+
+impl<B: Backend<R>, R: BackendResources> BackendDevice<B, R> {
+    /// Same as [`BackendDevice::new`], getting the ring through `resources`.
+    pub fn with_resources<T: XenStoreTransport>(
+        xs: &mut XenStore<T>,
+        frontend_id: DomId,
+        id: &str,
+        driver: B,
+        resources: R,
+    ) -> Result<Self, XenError> {
+        let nodename = XenbusPath::new(format_args!(
+            "backend/{}/{}/{id}",
+            B::DEVICE_TYPE,
+            frontend_id.0
+        ))?;
+
+        let mut buf = [0; super::XENBUS_PATH_MAX];
+        let frontend = XenbusPath::new(format_args!(
+            "{}",
+            xs.read_str(nodename.join("frontend")?.as_str(), &mut buf)?
+        ))?;
+
+        // The toolstack writes both, don't trust a mismatch.
+        if read_u32(xs, nodename.join("frontend-id")?.as_str())? != frontend_id.0 as u32 {
+            return Err(XenError::Inval);
+        }
+
+        let device = Self {
+            nodename,
+            frontend,
+            frontend_id,
+            state: XenbusState::Initialising,
+            token: XenbusPath::new(format_args!("be:{}/{}/{id}", B::DEVICE_TYPE, frontend_id.0))?,
+            ring: None,
+            resources,
+            driver: Some(driver),
+        };
+
+        write_state(xs, &device.nodename, XenbusState::Initialising)?;
+        xs.watch(
+            device.frontend.join("state")?.as_str(),
+            device.token.as_str(),
+            state_changed,
+        )?;
+
+        Ok(device)
+    }
+
+    /// State of the backend.
+    pub fn state(&self) -> XenbusState {
+        self.state
+    }
+
+    pub fn driver(&mut self) -> Option<&mut B> {
+        self.driver.as_mut()
+    }
+
+    /// Ring of the frontend, while connected.
+    pub fn ring(&self) -> Option<&BackendRing<R>> {
+        self.ring.as_ref()
+    }
+
+    /// `nodename/node`
+    pub fn backend_path(&self, node: &str) -> Result<XenbusPath, XenError> {
+        self.nodename.join(node)
+    }
+
+    /// `frontend/node`
+    pub fn frontend_path(&self, node: &str) -> Result<XenbusPath, XenError> {
+        self.frontend.join(node)
+    }
+
+    pub fn frontend_state(&self, xs: &mut impl XenStoreAccess) -> Result<XenbusState, XenError> {
+        read_state(xs, &self.frontend)
+    }
+
+    fn switch_state<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        state: XenbusState,
+    ) -> Result<(), XenError> {
+        write_state(xs, &self.nodename, state)?;
+        self.state = state;
+
+        Ok(())
+    }
+
+    /// Run `f` on the driver, which is taken out meanwhile to be able to give
+    /// it the device.
+    ///
+    /// Fails with EBUSY if the driver is already running.
+    fn call<T: XenStoreTransport, V>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        f: impl FnOnce(&mut B, &mut XenStore<T>, &Self) -> Result<V, XenError>,
+    ) -> Result<V, XenError> {
+        let mut driver = self.driver.take().ok_or(XenError::Busy)?;
+        let result = f(&mut driver, xs, self);
+        self.driver = Some(driver);
+
+        result
+    }
+
+    /// Tell the driver to stop using the device.
+    fn disconnect_driver<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+    ) -> Result<(), XenError> {
+        self.call(xs, |driver, xs, dev| {
+            driver.disconnect(xs, dev);
+            Ok(())
+        })
+    }
+
+    /// Map the ring and bind the event channel published by the frontend.
+    fn map_ring(&mut self, xs: &mut impl XenStoreAccess) -> Result<BackendRing<R>, XenError> {
+        let gref = read_u32(xs, self.frontend.join("ring-ref")?.as_str())?;
+        let port = read_u32(xs, self.frontend.join("event-channel")?.as_str())?;
+
+        let ring = self
+            .resources
+            .map_grant(self.frontend_id, GrantRef(gref), false)?;
+        let event_channel = self.resources.bind_interdomain(self.frontend_id, port)?;
+
+        Ok(BackendRing {
+            ring,
+            event_channel,
+        })
+    }
+
+    /// Stop the driver if connected, and release the ring.
+    fn release<T: XenStoreTransport>(&mut self, xs: &mut XenStore<T>) -> Result<(), XenError> {
+        if self.ring.is_some() {
+            self.disconnect_driver(xs)?;
+            self.ring = None;
+        }
+
+        Ok(())
+    }
+
+    /// Have the driver publish its features, then wait for the frontend.
+    fn probe<T: XenStoreTransport>(&mut self, xs: &mut XenStore<T>) -> Result<(), XenError> {
+        if let Err(e) = self.call(xs, |driver, xs, dev| driver.probe(xs, dev)) {
+            log::error!("{}: probe failed: {e}", self.nodename);
+            self.switch_state(xs, XenbusState::Closing)?;
+            return Err(e);
+        }
+
+        self.switch_state(xs, XenbusState::InitWait)
+    }
+
+    /// Handle the current frontend state, returns the new backend state.
+    pub fn update<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+    ) -> Result<XenbusState, XenError> {
+        loop {
+            let frontend = self.frontend_state(xs)?;
+
+            match (self.state, frontend) {
+                (XenbusState::Initialising, _) => self.probe(xs)?,
+                (XenbusState::InitWait, XenbusState::Initialised | XenbusState::Connected) => {
+                    let result = self.map_ring(xs).and_then(|ring| {
+                        self.ring = Some(ring);
+                        self.call(xs, |driver, xs, dev| driver.connect(xs, dev))
+                    });
+
+                    if let Err(e) = result {
+                        log::error!("{}: connect failed: {e}", self.nodename);
+                        self.ring = None;
+                        self.switch_state(xs, XenbusState::Closing)?;
+                        return Err(e);
+                    }
+
+                    self.switch_state(xs, XenbusState::Connected)?;
+                }
+                (XenbusState::InitWait | XenbusState::Connected, XenbusState::Closing) => {
+                    self.release(xs)?;
+                    self.switch_state(xs, XenbusState::Closing)?;
+                }
+                // The frontend node vanishes (Unknown) when the domain dies.
+                (
+                    XenbusState::InitWait | XenbusState::Connected | XenbusState::Closing,
+                    XenbusState::Closed,
+                )
+                | (
+                    XenbusState::InitWait | XenbusState::Connected | XenbusState::Closing,
+                    XenbusState::Unknown,
+                ) => {
+                    self.release(xs)?;
+                    self.switch_state(xs, XenbusState::Closed)?;
+                }
+                // The frontend restarts (e.g. after a reboot of its driver),
+                // publish the features again for it.
+                (XenbusState::Closed, XenbusState::Initialising) => self.probe(xs)?,
+                _ => return Ok(self.state),
+            }
+        }
+    }
+
+    /// Handle frontend changes until the backend reaches `state`.
+    ///
+    /// Fails with ENOTCONN if the device gets closed first.
+    pub fn wait_for<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        state: XenbusState,
+    ) -> Result<(), XenError> {
+        loop {
+            let current = self.update(xs)?;
+
+            if current == state {
+                return Ok(());
+            }
+
+            if current == XenbusState::Closed {
+                return Err(XenError::NotConn);
+            }
+
+            xs.wait_watches()?;
+        }
+    }
+
+    /// Close the device, waiting for the frontend to follow, and stop
+    /// watching it.
+    pub fn close<T: XenStoreTransport>(&mut self, xs: &mut XenStore<T>) -> Result<(), XenError> {
+        if self.state != XenbusState::Closed {
+            self.release(xs)?;
+            self.switch_state(xs, XenbusState::Closing)?;
+            self.wait_for(xs, XenbusState::Closed)
+                .or_else(|e| match e {
+                    XenError::NotConn => Ok(()),
+                    e => Err(e),
+                })?;
+        }
+
+        xs.unwatch(self.frontend.join("state")?.as_str(), self.token.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::xen::xenstore::fake::FakeXenStored;
+
+    const BACKEND: &str = "backend/vtest/1/0";
+    const FRONTEND: &str = "/local/domain/1/device/vtest/0";
+
+    /// Gives back the grant and port instead of mapping and binding them.
+    #[derive(Default)]
+    struct FakeResources {
+        fail_map: bool,
+    }
+
+    impl BackendResources for FakeResources {
+        type Mapping = (DomId, GrantRef);
+        type EventChannel = u32;
+
+        fn map_grant(
+            &mut self,
+            domid: DomId,
+            gref: GrantRef,
+            _readonly: bool,
+        ) -> Result<Self::Mapping, XenError> {
+            match self.fail_map {
+                true => Err(XenError::Inval),
+                false => Ok((domid, gref)),
+            }
+        }
+
+        fn bind_interdomain(&mut self, _domid: DomId, remote_port: u32) -> Result<u32, XenError> {
+            Ok(remote_port)
+        }
+    }
+
+    #[derive(Default)]
+    struct TestBackend {
+        calls: Vec<&'static str>,
+    }
+
+    impl Backend<FakeResources> for TestBackend {
+        const DEVICE_TYPE: &'static str = "vtest";
+
+        fn probe<T: XenStoreTransport>(
+            &mut self,
+            xs: &mut XenStore<T>,
+            device: &BackendDevice<Self, FakeResources>,
+        ) -> Result<(), XenError> {
+            self.calls.push("probe");
+            xs.write(device.backend_path("feature-test")?.as_str(), b"1")
+        }
+
+        fn connect<T: XenStoreTransport>(
+            &mut self,
+            _xs: &mut XenStore<T>,
+            device: &BackendDevice<Self, FakeResources>,
+        ) -> Result<(), XenError> {
+            let ring = device.ring().ok_or(XenError::NotConn)?;
+            assert_eq!(ring.ring, (DomId(1), GrantRef(8)));
+            assert_eq!(ring.event_channel, 5);
+
+            self.calls.push("connect");
+            Ok(())
+        }
+
+        fn disconnect<T: XenStoreTransport>(
+            &mut self,
+            _xs: &mut XenStore<T>,
+            device: &BackendDevice<Self, FakeResources>,
+        ) {
+            assert!(device.ring().is_some());
+            self.calls.push("disconnect");
+        }
+    }
+
+    type TestDevice = BackendDevice<TestBackend, FakeResources>;
+
+    fn setup(resources: FakeResources) -> (XenStore<FakeXenStored>, TestDevice) {
+        let mut xs = XenStore::new(FakeXenStored::default());
+        let store = xs.transport();
+
+        store.set(&format!("{BACKEND}/frontend"), FRONTEND.as_bytes());
+        store.set(&format!("{BACKEND}/frontend-id"), b"1");
+
+        let device = BackendDevice::with_resources(
+            &mut xs,
+            DomId(1),
+            "0",
+            TestBackend::default(),
+            resources,
+        )
+        .unwrap();
+
+        (xs, device)
+    }
+
+    /// Update the frontend state, as the frontend would.
+    fn set_frontend(xs: &mut XenStore<FakeXenStored>, state: XenbusState) {
+        let state = format!("{}", state as u32);
+
+        xs.transport()
+            .set(&format!("{FRONTEND}/state"), state.as_bytes());
+    }
+
+    fn backend_state(xs: &mut XenStore<FakeXenStored>) -> XenbusState {
+        read_state(xs, &XenbusPath::new(format_args!("{BACKEND}")).unwrap()).unwrap()
+    }
+
+    fn calls(device: &mut TestDevice) -> &[&'static str] {
+        &device.driver().unwrap().calls
+    }
+
+    #[test]
+    fn handshake() {
+        let (mut xs, mut device) = setup(FakeResources::default());
+
+        assert_eq!(device.state(), XenbusState::Initialising);
+        assert_eq!(backend_state(&mut xs), XenbusState::Initialising);
+        assert_eq!(xs.transport().watches.len(), 1);
+
+        set_frontend(&mut xs, XenbusState::Initialising);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::InitWait));
+        assert_eq!(backend_state(&mut xs), XenbusState::InitWait);
+        assert_eq!(
+            xs.read(&format!("{BACKEND}/feature-test"), &mut [0; 4]),
+            Ok(&b"1"[..])
+        );
+        assert_eq!(calls(&mut device), ["probe"]);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::InitWait));
+
+        xs.transport().set(&format!("{FRONTEND}/ring-ref"), b"8");
+        xs.transport()
+            .set(&format!("{FRONTEND}/event-channel"), b"5");
+        set_frontend(&mut xs, XenbusState::Initialised);
+
+        // The state changes were seen through the watch.
+        assert!(xs.dispatch_watches().unwrap() > 0);
+
+        device.wait_for(&mut xs, XenbusState::Connected).unwrap();
+        assert_eq!(backend_state(&mut xs), XenbusState::Connected);
+        assert!(device.ring().is_some());
+        assert_eq!(calls(&mut device), ["probe", "connect"]);
+
+        set_frontend(&mut xs, XenbusState::Closing);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::Closing));
+        assert_eq!(backend_state(&mut xs), XenbusState::Closing);
+        assert!(device.ring().is_none());
+        assert_eq!(calls(&mut device), ["probe", "connect", "disconnect"]);
+
+        set_frontend(&mut xs, XenbusState::Closed);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::Closed));
+        assert_eq!(backend_state(&mut xs), XenbusState::Closed);
+        assert_eq!(calls(&mut device), ["probe", "connect", "disconnect"]);
+
+        device.close(&mut xs).unwrap();
+        assert!(xs.transport().watches.is_empty());
+    }
+
+    #[test]
+    fn frontend_restart() {
+        let (mut xs, mut device) = setup(FakeResources::default());
+
+        set_frontend(&mut xs, XenbusState::Closed);
+        device.update(&mut xs).unwrap();
+        assert_eq!(
+            device.wait_for(&mut xs, XenbusState::Connected),
+            Err(XenError::NotConn)
+        );
+
+        xs.rm(&format!("{BACKEND}/feature-test")).unwrap();
+        set_frontend(&mut xs, XenbusState::Initialising);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::InitWait));
+        assert_eq!(backend_state(&mut xs), XenbusState::InitWait);
+
+        // The features were published again.
+        assert_eq!(calls(&mut device), ["probe", "probe"]);
+        assert_eq!(
+            xs.read(&format!("{BACKEND}/feature-test"), &mut [0; 4]),
+            Ok(&b"1"[..])
+        );
+    }
+
+    #[test]
+    fn frontend_gone() {
+        let (mut xs, mut device) = setup(FakeResources::default());
+
+        set_frontend(&mut xs, XenbusState::Initialising);
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::InitWait));
+
+        // The frontend domain dies before connecting.
+        xs.rm(FRONTEND).unwrap();
+        assert_eq!(device.update(&mut xs), Ok(XenbusState::Closed));
+        assert_eq!(backend_state(&mut xs), XenbusState::Closed);
+        assert_eq!(calls(&mut device), ["probe"]);
+    }
+
+    #[test]
+    fn map_failure() {
+        let (mut xs, mut device) = setup(FakeResources { fail_map: true });
+
+        set_frontend(&mut xs, XenbusState::Initialising);
+        device.update(&mut xs).unwrap();
+
+        xs.transport().set(&format!("{FRONTEND}/ring-ref"), b"8");
+        xs.transport()
+            .set(&format!("{FRONTEND}/event-channel"), b"5");
+        set_frontend(&mut xs, XenbusState::Initialised);
+
+        assert_eq!(device.update(&mut xs), Err(XenError::Inval));
+        assert_eq!(device.state(), XenbusState::Closing);
+        assert_eq!(backend_state(&mut xs), XenbusState::Closing);
+        assert!(device.ring().is_none());
+        assert_eq!(calls(&mut device), ["probe"]);
+    }
+
+    #[test]
+    fn frontend_id_mismatch() {
+        let mut xs = XenStore::new(FakeXenStored::default());
+
+        xs.transport()
+            .set(&format!("{BACKEND}/frontend"), FRONTEND.as_bytes());
+        xs.transport().set(&format!("{BACKEND}/frontend-id"), b"2");
+
+        let device = TestDevice::with_resources(
+            &mut xs,
+            DomId(1),
+            "0",
+            TestBackend::default(),
+            FakeResources::default(),
+        );
+        assert_eq!(device.err(), Some(XenError::Inval));
+    }
+}
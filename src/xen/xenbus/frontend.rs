@@ -0,0 +1,271 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenBus frontends
+//!
+//! [`FrontendDevice`] drives the frontend side of the handshake, calling the
+//! [`Frontend`] driver as the backend state changes:
+//!
+//! | backend                      | driver       | frontend     |
+//! |------------------------------|--------------|--------------|
+//! | InitWait (or later)          | `probe`      | Initialised  |
+//! | Connected                    | `connect`    | Connected    |
+//! | Closing/Closed               | `disconnect` | Closed       |
+
+use crate::xen::{
+    DomId, XenError,
+    xenstore::{XenStore, XenStoreAccess, XenStoreTransport, XsStrings},
+};
+
+use super::{XenbusPath, XenbusState, read_state, read_u32, state_changed, write_state};
+
+/// PV driver plugged into [`FrontendDevice`].
+pub trait Frontend {
+    /// Device type, as in `device/<type>/<id>`.
+    const DEVICE_TYPE: &'static str;
+
+    /// The backend waits for us: set up the shared resources (rings, event
+    /// channels, ...) and publish them in the frontend node.
+    fn probe<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &FrontendDevice<Self>,
+    ) -> Result<(), XenError>
+    where
+        Self: Sized;
+
+    /// The backend is connected, read what it published.
+    fn connect<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &FrontendDevice<Self>,
+    ) -> Result<(), XenError>
+    where
+        Self: Sized;
+
+    /// The backend is going away, release the shared resources.
+    fn disconnect<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        device: &FrontendDevice<Self>,
+    ) where
+        Self: Sized;
+}
+
+/// Identifiers of the `F::DEVICE_TYPE` devices, using `buf` for storage.
+pub fn enumerate<'b, F: Frontend>(
+    xs: &mut impl XenStoreAccess,
+    buf: &'b mut [u8],
+) -> Result<XsStrings<'b>, XenError> {
+    let path = XenbusPath::new(format_args!("device/{}", F::DEVICE_TYPE))?;
+
+    xs.directory(path.as_str(), buf)
+}
+
+/// Frontend side of a device
+pub struct FrontendDevice<F: Frontend> {
+    /// Frontend node (`device/<type>/<id>`)
+    pub nodename: XenbusPath,
+    /// Backend node
+    pub backend: XenbusPath,
+    pub backend_id: DomId,
+    state: XenbusState,
+    /// Watch token, for the backend state.
+    token: XenbusPath,
+    driver: Option<F>,
+}
+
+impl<F: Frontend> FrontendDevice<F> {
+    /// Take device `id` with `driver`, and start watching its backend.
+    ///
+    /// The driver is called by [`Self::update`] once the backend is ready.
+    pub fn new<T: XenStoreTransport>(
+        xs: &mut XenStore<T>,
+        id: &str,
+        driver: F,
+    ) -> Result<Self, XenError> {
+        let nodename = XenbusPath::new(format_args!("device/{}/{id}", F::DEVICE_TYPE))?;
+
+        let mut buf = [0; super::XENBUS_PATH_MAX];
+        let backend = XenbusPath::new(format_args!(
+            "{}",
+            xs.read_str(nodename.join("backend")?.as_str(), &mut buf)?
+        ))?;
+        let backend_id = read_u32(xs, nodename.join("backend-id")?.as_str())?;
+
+        let device = Self {
+            nodename,
+            backend,
+            backend_id: DomId(u16::try_from(backend_id).map_err(|_| XenError::Inval)?),
+            state: XenbusState::Initialising,
+            token: XenbusPath::new(format_args!("fe:{}/{id}", F::DEVICE_TYPE))?,
+            driver: Some(driver),
+        };
+
+        write_state(xs, &device.nodename, XenbusState::Initialising)?;
+        xs.watch(
+            device.backend.join("state")?.as_str(),
+            device.token.as_str(),
+            state_changed,
+        )?;
+
+        Ok(device)
+    }
+
+    /// State of the frontend.
+    pub fn state(&self) -> XenbusState {
+        self.state
+    }
+
+    pub fn driver(&mut self) -> Option<&mut F> {
+        self.driver.as_mut()
+    }
+
+    /// `nodename/node`
+    pub fn frontend_path(&self, node: &str) -> Result<XenbusPath, XenError> {
+        self.nodename.join(node)
+    }
+
+    /// `backend/node`
+    pub fn backend_path(&self, node: &str) -> Result<XenbusPath, XenError> {
+        self.backend.join(node)
+    }
+
+    pub fn backend_state(&self, xs: &mut impl XenStoreAccess) -> Result<XenbusState, XenError> {
+        read_state(xs, &self.backend)
+    }
+
+    fn switch_state<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        state: XenbusState,
+    ) -> Result<(), XenError> {
+        write_state(xs, &self.nodename, state)?;
+        self.state = state;
+
+        Ok(())
+    }
+
+    /// Run `f` on the driver, which is taken out meanwhile to be able to give
+    /// it the device.
+    ///
+    /// Fails with EBUSY if the driver is already running.
+    fn call<T: XenStoreTransport, R>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        f: impl FnOnce(&mut F, &mut XenStore<T>, &Self) -> Result<R, XenError>,
+    ) -> Result<R, XenError> {
+        let mut driver = self.driver.take().ok_or(XenError::Busy)?;
+        let result = f(&mut driver, xs, self);
+        self.driver = Some(driver);
+
+        result
+    }
+
+    /// Tell the driver to stop using the device.
+    fn disconnect_driver<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+    ) -> Result<(), XenError> {
+        self.call(xs, |driver, xs, dev| {
+            driver.disconnect(xs, dev);
+            Ok(())
+        })
+    }
+
+    /// Handle the current backend state, returns the new frontend state.
+    pub fn update<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+    ) -> Result<XenbusState, XenError> {
+        loop {
+            let backend = self.backend_state(xs)?;
+
+            match (self.state, backend) {
+                (
+                    XenbusState::Initialising,
+                    XenbusState::InitWait | XenbusState::Initialised | XenbusState::Connected,
+                ) => {
+                    if let Err(e) = self.call(xs, |driver, xs, dev| driver.probe(xs, dev)) {
+                        log::error!("{}: probe failed: {e}", self.nodename);
+                        self.switch_state(xs, XenbusState::Closing)?;
+                        return Err(e);
+                    }
+
+                    self.switch_state(xs, XenbusState::Initialised)?;
+                }
+                (XenbusState::Initialised, XenbusState::Connected) => {
+                    if let Err(e) = self.call(xs, |driver, xs, dev| driver.connect(xs, dev)) {
+                        log::error!("{}: connect failed: {e}", self.nodename);
+                        self.disconnect_driver(xs).ok();
+                        self.switch_state(xs, XenbusState::Closing)?;
+                        return Err(e);
+                    }
+
+                    self.switch_state(xs, XenbusState::Connected)?;
+                }
+                (
+                    XenbusState::Initialised | XenbusState::Connected | XenbusState::Closing,
+                    XenbusState::Closing | XenbusState::Closed,
+                ) => {
+                    if self.state != XenbusState::Closing {
+                        self.disconnect_driver(xs)?;
+                    }
+
+                    self.switch_state(xs, XenbusState::Closed)?;
+                }
+                (XenbusState::Initialising, XenbusState::Closing | XenbusState::Closed) => {
+                    self.switch_state(xs, XenbusState::Closed)?;
+                }
+                _ => return Ok(self.state),
+            }
+        }
+    }
+
+    /// Handle backend changes until the frontend reaches `state`.
+    ///
+    /// Fails with ENOTCONN if the device gets closed first.
+    pub fn wait_for<T: XenStoreTransport>(
+        &mut self,
+        xs: &mut XenStore<T>,
+        state: XenbusState,
+    ) -> Result<(), XenError> {
+        loop {
+            let current = self.update(xs)?;
+
+            if current == state {
+                return Ok(());
+            }
+
+            if current == XenbusState::Closed {
+                return Err(XenError::NotConn);
+            }
+
+            xs.wait_watches()?;
+        }
+    }
+
+    /// Bring the device up to Connected.
+    pub fn connect<T: XenStoreTransport>(&mut self, xs: &mut XenStore<T>) -> Result<(), XenError> {
+        self.wait_for(xs, XenbusState::Connected)
+    }
+
+    /// Close the device, waiting for the backend to follow, and stop
+    /// watching it.
+    pub fn close<T: XenStoreTransport>(&mut self, xs: &mut XenStore<T>) -> Result<(), XenError> {
+        if self.state != XenbusState::Closed {
+            if self.state != XenbusState::Initialising {
+                self.disconnect_driver(xs)?;
+            }
+
+            self.switch_state(xs, XenbusState::Closing)?;
+            self.wait_for(xs, XenbusState::Closed)
+                .or_else(|e| match e {
+                    XenError::NotConn => Ok(()),
+                    e => Err(e),
+                })?;
+        }
+
+        xs.unwatch(self.backend.join("state")?.as_str(), self.token.as_str())
+    }
+}
@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenBus, PV device discovery and handshake over XenStore
+//!
+//! Each PV device has a frontend node (`device/<type>/<id>` in the guest
+//! home) and a backend node (`backend/<type>/<frontend domid>/<id>` in the
+//! backend domain home), which point to each other. Both ends publish their
+//! [`XenbusState`] in a `state` node and move forward as the other end does.
+
+use core::{fmt, str};
+
+use crate::xen::{
+    XenError,
+    xenstore::{XenStore, XenStoreAccess, XenStoreTransport},
+};
+
+pub mod backend;
+pub mod frontend;
+
+pub use backend::{Backend, BackendDevice};
+pub use frontend::{Frontend, FrontendDevice};
+
+/// Maximum length of a XenBus path.
+pub const XENBUS_PATH_MAX: usize = 128;
+
+/// enum xenbus_state
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum XenbusState {
+    Unknown = 0,
+    Initialising = 1,
+    /// Backend waiting for the frontend information
+    InitWait = 2,
+    /// Frontend information published
+    Initialised = 3,
+    Connected = 4,
+    Closing = 5,
+    Closed = 6,
+    Reconfiguring = 7,
+    Reconfigured = 8,
+}
+
+impl From<u32> for XenbusState {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => XenbusState::Initialising,
+            2 => XenbusState::InitWait,
+            3 => XenbusState::Initialised,
+            4 => XenbusState::Connected,
+            5 => XenbusState::Closing,
+            6 => XenbusState::Closed,
+            7 => XenbusState::Reconfiguring,
+            8 => XenbusState::Reconfigured,
+            _ => XenbusState::Unknown,
+        }
+    }
+}
+
+/// XenStore path built in place.
+#[derive(Clone, Copy)]
+pub struct XenbusPath {
+    buf: [u8; XENBUS_PATH_MAX],
+    len: usize,
+}
+
+impl XenbusPath {
+    /// Format a path, fails with ENAMETOOLONG if it doesn't fit.
+    pub fn new(args: fmt::Arguments) -> Result<Self, XenError> {
+        let mut path = Self {
+            buf: [0; _],
+            len: 0,
+        };
+
+        fmt::Write::write_fmt(&mut path, args).map_err(|_| XenError::NameTooLong)?;
+
+        Ok(path)
+    }
+
+    /// `self/node`
+    pub fn join(&self, node: &str) -> Result<Self, XenError> {
+        Self::new(format_args!("{self}/{node}"))
+    }
+
+    pub fn as_str(&self) -> &str {
+        // SAFETY: Only built from strings.
+        unsafe { str::from_utf8_unchecked(&self.buf[..self.len]) }
+    }
+}
+
+impl fmt::Write for XenbusPath {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let dest = self
+            .buf
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(fmt::Error)?;
+
+        dest.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+
+        Ok(())
+    }
+}
+
+impl fmt::Display for XenbusPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Debug for XenbusPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+/// Read an integer node.
+pub fn read_u32(xs: &mut impl XenStoreAccess, path: &str) -> Result<u32, XenError> {
+    let mut buf = [0; 16];
+
+    xs.read_str(path, &mut buf)?
+        .trim()
+        .parse()
+        .map_err(|_| XenError::Inval)
+}
+
+/// Write an integer node.
+pub fn write_u32(xs: &mut impl XenStoreAccess, path: &str, value: u32) -> Result<(), XenError> {
+    let value = XenbusPath::new(format_args!("{value}"))?;
+
+    xs.write(path, value.as_str().as_bytes())
+}
+
+/// State published at `node/state`, Unknown if missing or invalid.
+pub fn read_state(
+    xs: &mut impl XenStoreAccess,
+    node: &XenbusPath,
+) -> Result<XenbusState, XenError> {
+    match read_u32(xs, node.join("state")?.as_str()) {
+        Ok(state) => Ok(state.into()),
+        Err(XenError::NoEnt | XenError::Inval) => Ok(XenbusState::Unknown),
+        Err(e) => Err(e),
+    }
+}
+
+/// Publish `state` at `node/state`.
+pub fn write_state(
+    xs: &mut impl XenStoreAccess,
+    node: &XenbusPath,
+    state: XenbusState,
+) -> Result<(), XenError> {
+    write_u32(xs, node.join("state")?.as_str(), state as u32)
+}
+
+/// Watch callback for the state of the other end.
+///
+/// It has nothing to do: the queued watch event is what wakes up
+/// [`XenStore::wait_watches`] in `wait_for`, and `update` then reads the
+/// new state itself.
+fn state_changed<T: XenStoreTransport>(_: &mut XenStore<T>, _: &str, _: &str) {}
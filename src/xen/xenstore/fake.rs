@@ -0,0 +1,239 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! In-memory xenstored for host tests
+//!
+//! [`FakeXenStored`] is a [`XenStoreTransport`] answering requests from a
+//! node map, as xenstored would. Raw messages (e.g. stale replies or watch
+//! events) can be queued before the next reply with [`FakeXenStored::push`].
+
+use std::{
+    collections::{BTreeMap, VecDeque},
+    string::{String, ToString},
+    vec::Vec,
+};
+
+use crate::xen::XenError;
+
+use super::{XSD_SOCKMSG_SIZE, XenStore, XenStoreTransport, XsdSockmsg, XsdSockmsgType};
+
+/// Raw message with header and payload.
+pub(crate) fn message(ty: XsdSockmsgType, req_id: u32, tx_id: u32, payload: &[u8]) -> Vec<u8> {
+    let header = XsdSockmsg {
+        ty,
+        req_id,
+        tx_id,
+        len: payload.len() as u32,
+    };
+
+    [&header.to_bytes()[..], payload].concat()
+}
+
+/// Split a NUL-terminated payload in its fields.
+pub(crate) fn fields(payload: &[u8]) -> Vec<&str> {
+    payload
+        .strip_suffix(b"\0")
+        .unwrap_or(payload)
+        .split(|&c| c == 0)
+        .map(|field| std::str::from_utf8(field).unwrap())
+        .collect()
+}
+
+/// Watch callback recording its calls in [`FakeXenStored::fired`].
+pub(crate) fn record(xs: &mut XenStore<FakeXenStored>, path: &str, token: &str) {
+    xs.transport()
+        .fired
+        .push((path.to_string(), token.to_string()));
+}
+
+#[derive(Default)]
+pub(crate) struct FakeXenStored {
+    /// Node values.
+    pub nodes: BTreeMap<String, Vec<u8>>,
+    /// Node permissions, as sent by SET_PERMS.
+    pub perms: BTreeMap<String, Vec<u8>>,
+    /// Watches (path, token).
+    pub watches: Vec<(String, String)>,
+    /// Requests received, header and payload.
+    pub requests: Vec<(XsdSockmsg, Vec<u8>)>,
+    /// Error returned to the next request of this type.
+    pub fail: Option<(XsdSockmsgType, &'static str)>,
+    /// Calls of [`record`] (path, token).
+    pub fired: Vec<(String, String)>,
+    /// Request being sent.
+    partial: Vec<u8>,
+    /// Messages to receive.
+    incoming: VecDeque<u8>,
+    next_tx_id: u32,
+}
+
+impl FakeXenStored {
+    /// Queue a raw message, received before the replies of the next requests.
+    pub fn push(&mut self, message: Vec<u8>) {
+        self.incoming.extend(message);
+    }
+
+    /// Set `path` as another domain would, firing the watches.
+    pub fn set(&mut self, path: &str, value: &[u8]) {
+        self.nodes.insert(path.to_string(), value.to_vec());
+        self.fire(path);
+    }
+
+    /// Whether `path` exists, parents of nodes being implicitly created.
+    fn exists(&self, path: &str) -> bool {
+        let prefix = format!("{path}/");
+
+        self.nodes
+            .keys()
+            .any(|node| node == path || node.starts_with(&prefix))
+    }
+
+    /// Queue an event for the watches on `path` or a parent.
+    fn fire(&mut self, path: &str) {
+        let events: Vec<_> = self
+            .watches
+            .iter()
+            .filter(|(watch, _)| {
+                path.strip_prefix(watch.as_str())
+                    .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+            })
+            .map(|(_, token)| message(XsdSockmsgType::WatchEvent, 0, 0, &event(path, token)))
+            .collect();
+
+        events.into_iter().for_each(|event| self.push(event));
+    }
+
+    fn children(&self, path: &str) -> Vec<u8> {
+        let prefix = format!("{path}/");
+        let mut children: Vec<&str> = self
+            .nodes
+            .keys()
+            .filter_map(|node| node.strip_prefix(&prefix))
+            .map(|child| child.split('/').next().unwrap())
+            .collect();
+        children.sort();
+        children.dedup();
+
+        children
+            .iter()
+            .flat_map(|child| [child.as_bytes(), b"\0"].concat())
+            .collect()
+    }
+
+    /// Handle a complete request, returns the reply payload.
+    fn handle(&mut self, ty: XsdSockmsgType, payload: &[u8]) -> Result<Vec<u8>, &'static str> {
+        if let Some((fail_ty, name)) = self.fail
+            && fail_ty == ty
+        {
+            self.fail = None;
+            return Err(name);
+        }
+
+        let fields = fields(payload);
+        let path = fields[0].to_string();
+
+        Ok(match ty {
+            XsdSockmsgType::Read => self.nodes.get(&path).ok_or("ENOENT")?.clone(),
+            XsdSockmsgType::Write => {
+                let value = &payload[path.len() + 1..];
+                self.set(&path, value);
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::Mkdir => {
+                if !self.exists(&path) {
+                    self.set(&path, b"");
+                }
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::Rm => {
+                let prefix = format!("{path}/");
+                self.nodes
+                    .retain(|node, _| *node != path && !node.starts_with(&prefix));
+                self.fire(&path);
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::Directory => {
+                if !self.exists(&path) {
+                    return Err("ENOENT");
+                }
+                self.children(&path)
+            }
+            XsdSockmsgType::GetPerms => self.perms.get(&path).cloned().unwrap_or(b"n0\0".to_vec()),
+            XsdSockmsgType::SetPerms => {
+                self.perms
+                    .insert(path.clone(), payload[path.len() + 1..].to_vec());
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::Watch => {
+                self.watches.push((path.clone(), fields[1].to_string()));
+                let initial = event(&path, fields[1]);
+                self.push(message(XsdSockmsgType::WatchEvent, 0, 0, &initial));
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::Unwatch => {
+                let watch = (path, fields[1].to_string());
+                self.watches.retain(|w| *w != watch);
+                b"OK\0".to_vec()
+            }
+            XsdSockmsgType::TransactionStart => {
+                self.next_tx_id += 1;
+                format!("{}\0", self.next_tx_id).into_bytes()
+            }
+            XsdSockmsgType::TransactionEnd => b"OK\0".to_vec(),
+            XsdSockmsgType::GetDomainPath => format!("/local/domain/{path}\0").into_bytes(),
+            _ => return Err("ENOSYS"),
+        })
+    }
+}
+
+fn event(path: &str, token: &str) -> Vec<u8> {
+    format!("{path}\0{token}\0").into_bytes()
+}
+
+impl XenStoreTransport for FakeXenStored {
+    fn send(&mut self, data: &[u8]) -> Result<(), XenError> {
+        self.partial.extend_from_slice(data);
+
+        while self.partial.len() >= XSD_SOCKMSG_SIZE {
+            let header =
+                XsdSockmsg::from_bytes(self.partial[..XSD_SOCKMSG_SIZE].try_into().unwrap());
+            let end = XSD_SOCKMSG_SIZE + header.len as usize;
+
+            if self.partial.len() < end {
+                break;
+            }
+
+            let payload: Vec<u8> = self.partial.drain(..end).skip(XSD_SOCKMSG_SIZE).collect();
+            let reply = match self.handle(header.ty, &payload) {
+                Ok(reply) => message(header.ty, header.req_id, header.tx_id, &reply),
+                Err(name) => message(
+                    XsdSockmsgType::Error,
+                    header.req_id,
+                    header.tx_id,
+                    format!("{name}\0").as_bytes(),
+                ),
+            };
+
+            self.requests.push((header, payload));
+            self.push(reply);
+        }
+
+        Ok(())
+    }
+
+    fn recv(&mut self, data: &mut [u8]) -> Result<(), XenError> {
+        // Nothing would ever come.
+        if self.incoming.len() < data.len() {
+            return Err(XenError::Io);
+        }
+
+        data.iter_mut()
+            .for_each(|byte| *byte = self.incoming.pop_front().unwrap());
+
+        Ok(())
+    }
+
+    fn has_data(&self) -> bool {
+        !self.incoming.is_empty()
+    }
+}
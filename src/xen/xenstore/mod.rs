@@ -0,0 +1,852 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenStore client
+//!
+//! Requests are framed with a xsd_sockmsg header and sent through a
+//! [`XenStoreTransport`], usually the store ring shared with xenstored
+//! ([`XenStoreRing`]). Replies are written to caller-provided buffers.
+
+use core::{fmt, str};
+
+use crate::xen::{DomId, XenError};
+
+#[cfg(test)]
+pub(crate) mod fake;
+mod ring;
+mod transaction;
+mod watch;
+
+pub use ring::XenStoreRing;
+pub use transaction::{XS_TRANSACTION_MAX_ATTEMPTS, XsTransaction};
+pub use watch::{WatchCallback, XS_MAX_QUEUED_EVENTS, XS_MAX_WATCHES, XS_WATCH_EVENT_MAX};
+
+use watch::Watches;
+
+/// Maximum payload of a message.
+pub const XENSTORE_PAYLOAD_MAX: usize = 4096;
+
+/// Maximum length of an absolute path.
+pub const XENSTORE_ABS_PATH_MAX: usize = 3072;
+
+/// Size of struct xsd_sockmsg.
+const XSD_SOCKMSG_SIZE: usize = 16;
+
+/// xsd_sockmsg_type
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XsdSockmsgType {
+    Control,
+    Directory,
+    Read,
+    GetPerms,
+    Watch,
+    Unwatch,
+    TransactionStart,
+    TransactionEnd,
+    Introduce,
+    Release,
+    GetDomainPath,
+    Write,
+    Mkdir,
+    Rm,
+    SetPerms,
+    WatchEvent,
+    Error,
+    Unknown(u32),
+}
+
+impl From<u32> for XsdSockmsgType {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => XsdSockmsgType::Control,
+            1 => XsdSockmsgType::Directory,
+            2 => XsdSockmsgType::Read,
+            3 => XsdSockmsgType::GetPerms,
+            4 => XsdSockmsgType::Watch,
+            5 => XsdSockmsgType::Unwatch,
+            6 => XsdSockmsgType::TransactionStart,
+            7 => XsdSockmsgType::TransactionEnd,
+            8 => XsdSockmsgType::Introduce,
+            9 => XsdSockmsgType::Release,
+            10 => XsdSockmsgType::GetDomainPath,
+            11 => XsdSockmsgType::Write,
+            12 => XsdSockmsgType::Mkdir,
+            13 => XsdSockmsgType::Rm,
+            14 => XsdSockmsgType::SetPerms,
+            15 => XsdSockmsgType::WatchEvent,
+            16 => XsdSockmsgType::Error,
+            t => XsdSockmsgType::Unknown(t),
+        }
+    }
+}
+
+impl From<XsdSockmsgType> for u32 {
+    fn from(value: XsdSockmsgType) -> Self {
+        match value {
+            XsdSockmsgType::Control => 0,
+            XsdSockmsgType::Directory => 1,
+            XsdSockmsgType::Read => 2,
+            XsdSockmsgType::GetPerms => 3,
+            XsdSockmsgType::Watch => 4,
+            XsdSockmsgType::Unwatch => 5,
+            XsdSockmsgType::TransactionStart => 6,
+            XsdSockmsgType::TransactionEnd => 7,
+            XsdSockmsgType::Introduce => 8,
+            XsdSockmsgType::Release => 9,
+            XsdSockmsgType::GetDomainPath => 10,
+            XsdSockmsgType::Write => 11,
+            XsdSockmsgType::Mkdir => 12,
+            XsdSockmsgType::Rm => 13,
+            XsdSockmsgType::SetPerms => 14,
+            XsdSockmsgType::WatchEvent => 15,
+            XsdSockmsgType::Error => 16,
+            XsdSockmsgType::Unknown(t) => t,
+        }
+    }
+}
+
+/// Message header (struct xsd_sockmsg)
+#[derive(Clone, Copy, Debug)]
+pub struct XsdSockmsg {
+    pub ty: XsdSockmsgType,
+    pub req_id: u32,
+    pub tx_id: u32,
+    pub len: u32,
+}
+
+impl XsdSockmsg {
+    fn to_bytes(self) -> [u8; XSD_SOCKMSG_SIZE] {
+        let mut raw = [0; XSD_SOCKMSG_SIZE];
+
+        raw[0..4].copy_from_slice(&u32::from(self.ty).to_ne_bytes());
+        raw[4..8].copy_from_slice(&self.req_id.to_ne_bytes());
+        raw[8..12].copy_from_slice(&self.tx_id.to_ne_bytes());
+        raw[12..16].copy_from_slice(&self.len.to_ne_bytes());
+
+        raw
+    }
+
+    fn from_bytes(raw: &[u8; XSD_SOCKMSG_SIZE]) -> Self {
+        let word = |i: usize| u32::from_ne_bytes(raw[i..i + 4].try_into().unwrap());
+
+        Self {
+            ty: word(0).into(),
+            req_id: word(4),
+            tx_id: word(8),
+            len: word(12),
+        }
+    }
+}
+
+/// Byte stream to xenstored.
+pub trait XenStoreTransport {
+    /// Send the whole `data`, blocking until done.
+    fn send(&mut self, data: &[u8]) -> Result<(), XenError>;
+
+    /// Fill the whole `data`, blocking until done.
+    fn recv(&mut self, data: &mut [u8]) -> Result<(), XenError>;
+
+    /// Whether some data can be received without blocking.
+    fn has_data(&self) -> bool;
+}
+
+/// Access rights of a domain on a node
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum XsAccess {
+    None,
+    Read,
+    Write,
+    ReadWrite,
+}
+
+/// Node permission, the first one of a node gives its owner and the
+/// access of unlisted domains.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct XsPermission {
+    pub domid: DomId,
+    pub access: XsAccess,
+}
+
+impl XsPermission {
+    fn parse(perm: &str) -> Option<Self> {
+        let access = match perm.as_bytes().first()? {
+            b'n' => XsAccess::None,
+            b'r' => XsAccess::Read,
+            b'w' => XsAccess::Write,
+            b'b' => XsAccess::ReadWrite,
+            _ => return None,
+        };
+
+        Some(Self {
+            domid: DomId(perm[1..].parse().ok()?),
+            access,
+        })
+    }
+}
+
+impl fmt::Display for XsPermission {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let access = match self.access {
+            XsAccess::None => 'n',
+            XsAccess::Read => 'r',
+            XsAccess::Write => 'w',
+            XsAccess::ReadWrite => 'b',
+        };
+
+        write!(f, "{access}{}", self.domid.0)
+    }
+}
+
+/// List of NUL-terminated strings (directory entries, permissions).
+///
+/// Entries that aren't valid UTF-8 are skipped, which xenstored doesn't
+/// allow anyway.
+#[derive(Clone, Copy, Debug)]
+pub struct XsStrings<'a> {
+    content: &'a [u8],
+}
+
+impl<'a> Iterator for XsStrings<'a> {
+    type Item = &'a str;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if self.content.is_empty() {
+                return None;
+            }
+
+            let end = self
+                .content
+                .iter()
+                .position(|&c| c == 0)
+                .unwrap_or(self.content.len());
+            let entry = &self.content[..end];
+            self.content = self.content.get(end + 1..).unwrap_or_default();
+
+            if let Ok(entry) = str::from_utf8(entry) {
+                return Some(entry);
+            }
+        }
+    }
+}
+
+/// Small buffer used to format payloads.
+struct PayloadBuf<const N: usize> {
+    buf: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> PayloadBuf<N> {
+    fn new() -> Self {
+        Self {
+            buf: [0; N],
+            len: 0,
+        }
+    }
+
+    fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl<const N: usize> fmt::Write for PayloadBuf<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let dest = self
+            .buf
+            .get_mut(self.len..self.len + s.len())
+            .ok_or(fmt::Error)?;
+
+        dest.copy_from_slice(s.as_bytes());
+        self.len += s.len();
+
+        Ok(())
+    }
+}
+
+/// XenStore connection
+pub struct XenStore<T: XenStoreTransport> {
+    transport: T,
+    next_req_id: u32,
+    watches: Watches<T>,
+}
+
+impl<T: XenStoreTransport> XenStore<T> {
+    pub fn new(transport: T) -> Self {
+        Self {
+            transport,
+            next_req_id: 0,
+            watches: Watches::new(),
+        }
+    }
+
+    pub fn transport(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    /// Send a request made of the concatenation of `parts`, returns its id.
+    fn send_request(
+        &mut self,
+        tx_id: u32,
+        ty: XsdSockmsgType,
+        parts: &[&[u8]],
+    ) -> Result<u32, XenError> {
+        let len: usize = parts.iter().map(|part| part.len()).sum();
+
+        if len > XENSTORE_PAYLOAD_MAX {
+            return Err(XenError::MsgSize);
+        }
+
+        let req_id = self.next_req_id;
+        self.next_req_id = self.next_req_id.wrapping_add(1);
+
+        let header = XsdSockmsg {
+            ty,
+            req_id,
+            tx_id,
+            len: len as u32,
+        };
+
+        self.transport.send(&header.to_bytes())?;
+        for part in parts {
+            self.transport.send(part)?;
+        }
+
+        Ok(req_id)
+    }
+
+    /// Receive `len` bytes of payload and throw them away.
+    fn discard(&mut self, mut len: usize) -> Result<(), XenError> {
+        let mut scratch = [0; 64];
+
+        while len > 0 {
+            let chunk = len.min(scratch.len());
+            self.transport.recv(&mut scratch[..chunk])?;
+            len -= chunk;
+        }
+
+        Ok(())
+    }
+
+    /// Receive the header of the next message, watch events are queued and
+    /// reported as None.
+    fn recv_header(&mut self) -> Result<Option<XsdSockmsg>, XenError> {
+        let mut raw = [0; XSD_SOCKMSG_SIZE];
+        self.transport.recv(&mut raw)?;
+
+        let header = XsdSockmsg::from_bytes(&raw);
+        let len = header.len as usize;
+
+        if len > XENSTORE_PAYLOAD_MAX {
+            return Err(XenError::BadMsg);
+        }
+
+        if header.ty == XsdSockmsgType::WatchEvent {
+            self.queue_watch_event(len)?;
+            return Ok(None);
+        }
+
+        Ok(Some(header))
+    }
+
+    /// Wait for the reply to request `req_id` of type `ty`, its payload is
+    /// written to `reply`.
+    fn recv_reply<'b>(
+        &mut self,
+        req_id: u32,
+        ty: XsdSockmsgType,
+        reply: &'b mut [u8],
+    ) -> Result<&'b mut [u8], XenError> {
+        loop {
+            let Some(header) = self.recv_header()? else {
+                continue;
+            };
+            let len = header.len as usize;
+
+            // Not for us, skip it.
+            if header.req_id != req_id {
+                log::debug!("XenStore: dropping unexpected {:?} message", header.ty);
+                self.discard(len)?;
+                continue;
+            }
+
+            if header.ty == XsdSockmsgType::Error {
+                let mut name = [0; 16];
+                let name_len = len.min(name.len());
+
+                self.transport.recv(&mut name[..name_len])?;
+                self.discard(len - name_len)?;
+
+                let name = name[..name_len].split(|&c| c == 0).next().unwrap_or(&[]);
+
+                return Err(str::from_utf8(name)
+                    .ok()
+                    .and_then(XenError::from_name)
+                    .unwrap_or(XenError::Io));
+            }
+
+            if header.ty != ty {
+                self.discard(len)?;
+                return Err(XenError::BadMsg);
+            }
+
+            if len > reply.len() {
+                self.discard(len)?;
+                return Err(XenError::NoBufs);
+            }
+
+            let reply = &mut reply[..len];
+            self.transport.recv(reply)?;
+
+            return Ok(reply);
+        }
+    }
+
+    /// Issue a request and wait for its reply.
+    fn request<'b>(
+        &mut self,
+        tx_id: u32,
+        ty: XsdSockmsgType,
+        parts: &[&[u8]],
+        reply: &'b mut [u8],
+    ) -> Result<&'b mut [u8], XenError> {
+        let req_id = self.send_request(tx_id, ty, parts)?;
+
+        self.recv_reply(req_id, ty, reply)
+    }
+
+    /// Issue a request whose reply is just "OK".
+    fn request_ok(
+        &mut self,
+        tx_id: u32,
+        ty: XsdSockmsgType,
+        parts: &[&[u8]],
+    ) -> Result<(), XenError> {
+        let mut reply = [0; 8];
+
+        self.request(tx_id, ty, parts, &mut reply)?;
+
+        Ok(())
+    }
+
+    pub(crate) fn read_in<'b>(
+        &mut self,
+        tx_id: u32,
+        path: &str,
+        buf: &'b mut [u8],
+    ) -> Result<&'b [u8], XenError> {
+        let parts = [path.as_bytes(), b"\0"];
+
+        Ok(self.request(tx_id, XsdSockmsgType::Read, &parts, buf)?)
+    }
+
+    pub(crate) fn write_in(
+        &mut self,
+        tx_id: u32,
+        path: &str,
+        value: &[u8],
+    ) -> Result<(), XenError> {
+        let parts = [path.as_bytes(), b"\0", value];
+
+        self.request_ok(tx_id, XsdSockmsgType::Write, &parts)
+    }
+
+    pub(crate) fn directory_in<'b>(
+        &mut self,
+        tx_id: u32,
+        path: &str,
+        buf: &'b mut [u8],
+    ) -> Result<XsStrings<'b>, XenError> {
+        let parts = [path.as_bytes(), b"\0"];
+        let content = self.request(tx_id, XsdSockmsgType::Directory, &parts, buf)?;
+
+        Ok(XsStrings { content })
+    }
+
+    pub(crate) fn mkdir_in(&mut self, tx_id: u32, path: &str) -> Result<(), XenError> {
+        self.request_ok(tx_id, XsdSockmsgType::Mkdir, &[path.as_bytes(), b"\0"])
+    }
+
+    pub(crate) fn rm_in(&mut self, tx_id: u32, path: &str) -> Result<(), XenError> {
+        self.request_ok(tx_id, XsdSockmsgType::Rm, &[path.as_bytes(), b"\0"])
+    }
+
+    pub(crate) fn get_perms_in<'b>(
+        &mut self,
+        tx_id: u32,
+        path: &str,
+        buf: &'b mut [u8],
+    ) -> Result<impl Iterator<Item = XsPermission> + 'b, XenError> {
+        let parts = [path.as_bytes(), b"\0"];
+        let content = self.request(tx_id, XsdSockmsgType::GetPerms, &parts, buf)?;
+
+        Ok(XsStrings { content }.filter_map(XsPermission::parse))
+    }
+
+    pub(crate) fn set_perms_in(
+        &mut self,
+        tx_id: u32,
+        path: &str,
+        perms: &[XsPermission],
+    ) -> Result<(), XenError> {
+        let mut payload = PayloadBuf::<512>::new();
+
+        for perm in perms {
+            fmt::Write::write_fmt(&mut payload, format_args!("{perm}\0"))
+                .map_err(|_| XenError::TooBig)?;
+        }
+
+        let parts = [path.as_bytes(), b"\0", payload.as_bytes()];
+
+        self.request_ok(tx_id, XsdSockmsgType::SetPerms, &parts)
+    }
+
+    /// Home path of `domid` (e.g. `/local/domain/1`).
+    pub fn get_domain_path<'b>(
+        &mut self,
+        domid: DomId,
+        buf: &'b mut [u8],
+    ) -> Result<&'b str, XenError> {
+        let mut domid_str = PayloadBuf::<8>::new();
+        fmt::Write::write_fmt(&mut domid_str, format_args!("{}", domid.0))
+            .map_err(|_| XenError::Inval)?;
+
+        let parts = [domid_str.as_bytes(), b"\0"];
+        let path = self.request(0, XsdSockmsgType::GetDomainPath, &parts, buf)?;
+        let path = path.split(|&c| c == 0).next().unwrap_or_default();
+
+        str::from_utf8(path).map_err(|_| XenError::BadMsg)
+    }
+}
+
+/// Node operations, available on the connection itself and within a
+/// transaction ([`XsTransaction`]).
+pub trait XenStoreAccess {
+    type Transport: XenStoreTransport;
+
+    /// Connection and transaction id (0 outside of a transaction) to use.
+    fn context(&mut self) -> (&mut XenStore<Self::Transport>, u32);
+
+    /// Read the value of `path` into `buf`.
+    fn read<'b>(&mut self, path: &str, buf: &'b mut [u8]) -> Result<&'b [u8], XenError> {
+        let (xs, tx_id) = self.context();
+        xs.read_in(tx_id, path, buf)
+    }
+
+    /// Read the value of `path` into `buf` as a string.
+    fn read_str<'b>(&mut self, path: &str, buf: &'b mut [u8]) -> Result<&'b str, XenError> {
+        str::from_utf8(self.read(path, buf)?).map_err(|_| XenError::BadMsg)
+    }
+
+    /// Set the value of `path`, creating it if needed.
+    fn write(&mut self, path: &str, value: &[u8]) -> Result<(), XenError> {
+        let (xs, tx_id) = self.context();
+        xs.write_in(tx_id, path, value)
+    }
+
+    /// List the children of `path`, using `buf` for storage.
+    fn directory<'b>(&mut self, path: &str, buf: &'b mut [u8]) -> Result<XsStrings<'b>, XenError> {
+        let (xs, tx_id) = self.context();
+        xs.directory_in(tx_id, path, buf)
+    }
+
+    /// Create `path` (and its parents), with an empty value.
+    fn mkdir(&mut self, path: &str) -> Result<(), XenError> {
+        let (xs, tx_id) = self.context();
+        xs.mkdir_in(tx_id, path)
+    }
+
+    /// Remove `path` and its children.
+    fn rm(&mut self, path: &str) -> Result<(), XenError> {
+        let (xs, tx_id) = self.context();
+        xs.rm_in(tx_id, path)
+    }
+
+    /// Permissions of `path`, using `buf` for storage.
+    fn get_perms<'b>(
+        &mut self,
+        path: &str,
+        buf: &'b mut [u8],
+    ) -> Result<impl Iterator<Item = XsPermission> + 'b, XenError> {
+        let (xs, tx_id) = self.context();
+        xs.get_perms_in(tx_id, path, buf)
+    }
+
+    /// Replace the permissions of `path`.
+    fn set_perms(&mut self, path: &str, perms: &[XsPermission]) -> Result<(), XenError> {
+        let (xs, tx_id) = self.context();
+        xs.set_perms_in(tx_id, path, perms)
+    }
+}
+
+impl<T: XenStoreTransport> XenStoreAccess for XenStore<T> {
+    type Transport = T;
+
+    fn context(&mut self) -> (&mut XenStore<T>, u32) {
+        (self, 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        fake::{FakeXenStored, fields, message, record},
+        *,
+    };
+
+    fn xenstore() -> XenStore<FakeXenStored> {
+        XenStore::new(FakeXenStored::default())
+    }
+
+    /// Check the last request sent.
+    fn assert_request(
+        xs: &mut XenStore<FakeXenStored>,
+        ty: XsdSockmsgType,
+        tx_id: u32,
+        payload: &[u8],
+    ) {
+        let requests = &xs.transport().requests;
+        let (header, sent) = requests.last().unwrap();
+
+        assert_eq!(header.ty, ty);
+        assert_eq!(header.req_id, requests.len() as u32 - 1);
+        assert_eq!(header.tx_id, tx_id);
+        assert_eq!(header.len as usize, sent.len());
+        assert_eq!(sent, payload);
+    }
+
+    #[test]
+    fn header() {
+        let header = XsdSockmsg {
+            ty: XsdSockmsgType::Write,
+            req_id: 0x1234,
+            tx_id: 7,
+            len: 42,
+        };
+        let raw = header.to_bytes();
+
+        assert_eq!(raw[0..4], 11u32.to_ne_bytes());
+        assert_eq!(raw[4..8], 0x1234u32.to_ne_bytes());
+        assert_eq!(raw[8..12], 7u32.to_ne_bytes());
+        assert_eq!(raw[12..16], 42u32.to_ne_bytes());
+
+        let parsed = XsdSockmsg::from_bytes(&raw);
+        assert_eq!(parsed.ty, XsdSockmsgType::Write);
+        assert_eq!((parsed.req_id, parsed.tx_id, parsed.len), (0x1234, 7, 42));
+
+        for ty in 0..=17 {
+            assert_eq!(u32::from(XsdSockmsgType::from(ty)), ty);
+        }
+    }
+
+    #[test]
+    fn framing() {
+        let mut xs = xenstore();
+        let mut buf = [0; 64];
+
+        xs.write("data/key", b"value").unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Write, 0, b"data/key\0value");
+
+        assert_eq!(xs.read("data/key", &mut buf), Ok(&b"value"[..]));
+        assert_request(&mut xs, XsdSockmsgType::Read, 0, b"data/key\0");
+
+        xs.mkdir("data/dir").unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Mkdir, 0, b"data/dir\0");
+
+        xs.directory("data", &mut buf).unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Directory, 0, b"data\0");
+
+        xs.rm("data/dir").unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Rm, 0, b"data/dir\0");
+
+        let perms = [
+            XsPermission {
+                domid: DomId(0),
+                access: XsAccess::None,
+            },
+            XsPermission {
+                domid: DomId(12),
+                access: XsAccess::Read,
+            },
+        ];
+        xs.set_perms("data/key", &perms).unwrap();
+        assert_request(&mut xs, XsdSockmsgType::SetPerms, 0, b"data/key\0n0\0r12\0");
+
+        assert_eq!(xs.get_perms("data/key", &mut buf).unwrap().count(), 2);
+        assert_request(&mut xs, XsdSockmsgType::GetPerms, 0, b"data/key\0");
+
+        assert_eq!(
+            xs.get_domain_path(DomId(3), &mut buf),
+            Ok("/local/domain/3")
+        );
+        assert_request(&mut xs, XsdSockmsgType::GetDomainPath, 0, b"3\0");
+
+        xs.watch("data", "tok", record).unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Watch, 0, b"data\0tok\0");
+
+        xs.unwatch("data", "tok").unwrap();
+        assert_request(&mut xs, XsdSockmsgType::Unwatch, 0, b"data\0tok\0");
+    }
+
+    #[test]
+    fn transaction() {
+        let mut xs = xenstore();
+        let mut attempts = 0;
+
+        // The first commit conflicts.
+        xs.transport().fail = Some((XsdSockmsgType::TransactionEnd, "EAGAIN"));
+
+        xs.transaction(|tx| {
+            attempts += 1;
+            tx.write("data/key", b"1")
+        })
+        .unwrap();
+
+        assert_eq!(attempts, 2);
+
+        let requests = &xs.transport().requests;
+        let sent: Vec<_> = requests
+            .iter()
+            .map(|(header, payload)| (header.ty, header.tx_id, payload.as_slice()))
+            .collect();
+
+        assert_eq!(
+            sent,
+            [
+                (XsdSockmsgType::TransactionStart, 0, &b"\0"[..]),
+                (XsdSockmsgType::Write, 1, b"data/key\01"),
+                (XsdSockmsgType::TransactionEnd, 1, b"T\0"),
+                (XsdSockmsgType::TransactionStart, 0, b"\0"),
+                (XsdSockmsgType::Write, 2, b"data/key\01"),
+                (XsdSockmsgType::TransactionEnd, 2, b"T\0"),
+            ]
+        );
+    }
+
+    #[test]
+    fn match_req_id() {
+        let mut xs = xenstore();
+        let mut buf = [0; 64];
+
+        xs.watch("data", "tok", record).unwrap();
+        xs.transport().set("data/key", b"new");
+
+        // A stale reply and a watch event come before the reply.
+        let stale = message(XsdSockmsgType::Read, 42, 0, b"old");
+        let event = message(XsdSockmsgType::WatchEvent, 0, 0, b"data/other\0tok\0");
+        xs.transport().push(stale);
+        xs.transport().push(event);
+
+        assert_eq!(xs.read("data/key", &mut buf), Ok(&b"new"[..]));
+        assert!(xs.transport().fired.is_empty());
+
+        // Initial event, the write, then the interleaved one.
+        assert_eq!(xs.dispatch_watches(), Ok(3));
+        let fired: Vec<_> = xs
+            .transport()
+            .fired
+            .iter()
+            .map(|(path, token)| (path.as_str(), token.as_str()))
+            .collect();
+        assert_eq!(
+            fired,
+            [("data", "tok"), ("data/key", "tok"), ("data/other", "tok")]
+        );
+
+        // Nothing left to receive.
+        assert!(!xs.transport().has_data());
+    }
+
+    #[test]
+    fn unexpected_reply() {
+        let mut xs = xenstore();
+        let mut buf = [0; 4];
+
+        // Same id, but not the type of the request.
+        xs.transport()
+            .push(message(XsdSockmsgType::Write, 0, 0, b"OK\0"));
+        assert_eq!(xs.read("data", &mut buf), Err(XenError::BadMsg));
+
+        // The real reply is then dropped as a stale one.
+        xs.transport().set("data", b"too long");
+        assert_eq!(xs.read("data", &mut buf), Err(XenError::NoBufs));
+        assert!(!xs.transport().has_data());
+    }
+
+    #[test]
+    fn errors() {
+        let mut xs = xenstore();
+        let mut buf = [0; 64];
+
+        assert_eq!(xs.read("missing", &mut buf), Err(XenError::NoEnt));
+
+        xs.transport().fail = Some((XsdSockmsgType::Write, "EACCES"));
+        assert_eq!(xs.write("data", b""), Err(XenError::Access));
+
+        // Unknown errors, and names longer than any known one.
+        xs.transport().fail = Some((XsdSockmsgType::Mkdir, "EWHATEVER"));
+        assert_eq!(xs.mkdir("data"), Err(XenError::Io));
+
+        xs.transport().fail = Some((XsdSockmsgType::Rm, "ENOTANERRORNAMEATALL"));
+        assert_eq!(xs.rm("data"), Err(XenError::Io));
+
+        // The connection is still usable.
+        xs.write("data", b"ok").unwrap();
+        assert!(!xs.transport().has_data());
+    }
+
+    #[test]
+    fn directory() {
+        let mut xs = xenstore();
+        let mut buf = [0; 64];
+
+        for path in ["dev/vif/0/state", "dev/vif/1", "dev/vbd/768", "devices"] {
+            xs.transport().set(path, b"");
+        }
+
+        let entries: Vec<_> = xs.directory("dev", &mut buf).unwrap().collect();
+        assert_eq!(entries, ["vbd", "vif"]);
+
+        let entries: Vec<_> = xs.directory("dev/vif", &mut buf).unwrap().collect();
+        assert_eq!(entries, ["0", "1"]);
+
+        // Empty, and invalid UTF-8 entries.
+        assert_eq!(XsStrings { content: b"" }.count(), 0);
+        let entries: Vec<_> = XsStrings {
+            content: b"a\0\xff\0b",
+        }
+        .collect();
+        assert_eq!(entries, ["a", "b"]);
+    }
+
+    #[test]
+    fn get_perms() {
+        let mut xs = xenstore();
+        let mut buf = [0; 64];
+
+        xs.transport()
+            .perms
+            .insert("data".into(), b"b1\0r0\0w32752\0x3\0n\0".to_vec());
+
+        let perms: Vec<_> = xs.get_perms("data", &mut buf).unwrap().collect();
+        assert_eq!(
+            perms,
+            [
+                XsPermission {
+                    domid: DomId(1),
+                    access: XsAccess::ReadWrite,
+                },
+                XsPermission {
+                    domid: DomId(0),
+                    access: XsAccess::Read,
+                },
+                XsPermission {
+                    domid: DomId::SELF,
+                    access: XsAccess::Write,
+                },
+            ]
+        );
+
+        let formatted: Vec<_> = perms.iter().map(|perm| perm.to_string()).collect();
+        assert_eq!(fields(b"b1\0r0\0w32752\0"), formatted);
+    }
+}
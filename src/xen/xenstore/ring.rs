@@ -0,0 +1,137 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! Store ring transport (struct xenstore_domain_interface)
+
+use core::sync::atomic::AtomicU32;
+
+use volatile::{VolatileFieldAccess, VolatilePtr};
+
+use crate::{
+    arch::map_4k_frame,
+    xen::{
+        XenError,
+        event::EventChannel,
+        hvm::{HvmParam, get_param},
+        ring::{XenRing, XenRingError},
+        sched::{deadline_after, wait_for_event},
+    },
+};
+
+use super::XenStoreTransport;
+
+/// Longest wait for xenstored before checking the rings again.
+const XENSTORE_WAIT_NS: u64 = 10_000_000;
+
+#[repr(C)]
+#[derive(VolatileFieldAccess)]
+pub struct XenStoreDomainInterface {
+    req: [u8; 1024],
+    rsp: [u8; 1024],
+    req_cons: u32,
+    req_prod: u32,
+    rsp_cons: u32,
+    rsp_prod: u32,
+    server_features: u32,
+    connection: u32,
+    error: u32,
+}
+
+/// Store ring shared with xenstored, found through HVM_PARAM_STORE_PFN and
+/// HVM_PARAM_STORE_EVTCHN.
+pub struct XenStoreRing {
+    req: XenRing<'static>,
+    rsp: XenRing<'static>,
+    event_channel: EventChannel,
+}
+
+unsafe impl Send for XenStoreRing {}
+unsafe impl Sync for XenStoreRing {}
+
+fn ring_error(_: XenRingError) -> XenError {
+    XenError::Io
+}
+
+impl XenStoreRing {
+    /// # Safety
+    ///
+    /// There must be a single user of the store ring.
+    pub unsafe fn new() -> Option<Self> {
+        let pfn = get_param(HvmParam::StorePfn).ok()?;
+        let evtchn = get_param(HvmParam::StoreEvtchn).ok()?;
+
+        if pfn == 0 {
+            return None;
+        }
+
+        // Xen doesn't know about memory encryption, access it as shared memory.
+        let interface: VolatilePtr<XenStoreDomainInterface> =
+            unsafe { VolatilePtr::new(map_4k_frame(pfn, false)?) };
+
+        let atomic =
+            |ptr: VolatilePtr<u32>| unsafe { AtomicU32::from_ptr(ptr.as_raw_ptr().as_ptr()) };
+
+        Some(Self {
+            req: XenRing {
+                ring: interface.req().as_slice(),
+                cons: atomic(interface.req_cons()),
+                prod: atomic(interface.req_prod()),
+            },
+            rsp: XenRing {
+                ring: interface.rsp().as_slice(),
+                cons: atomic(interface.rsp_cons()),
+                prod: atomic(interface.rsp_prod()),
+            },
+            event_channel: EventChannel(evtchn as u32),
+        })
+    }
+
+    pub fn event_channel(&self) -> EventChannel {
+        self.event_channel
+    }
+
+    /// Sleep until xenstored notifies us, or a bit of time has passed.
+    fn wait(&self) {
+        wait_for_event(self.event_channel, deadline_after(XENSTORE_WAIT_NS)).ok();
+    }
+}
+
+impl XenStoreTransport for XenStoreRing {
+    fn send(&mut self, mut data: &[u8]) -> Result<(), XenError> {
+        while !data.is_empty() {
+            let chunk = data.len().min(self.req.available().map_err(ring_error)?);
+
+            if chunk == 0 {
+                self.event_channel.send()?;
+                self.wait();
+                continue;
+            }
+
+            self.req.write(&data[..chunk]).map_err(ring_error)?;
+            data = &data[chunk..];
+        }
+
+        self.event_channel.send()
+    }
+
+    fn recv(&mut self, mut data: &mut [u8]) -> Result<(), XenError> {
+        while !data.is_empty() {
+            let count = self.rsp.read(data).map_err(ring_error)?;
+
+            if count == 0 {
+                self.wait();
+                continue;
+            }
+
+            // Let xenstored know there is room for more.
+            self.event_channel.send()?;
+            data = &mut data[count..];
+        }
+
+        Ok(())
+    }
+
+    fn has_data(&self) -> bool {
+        self.rsp.queued().is_ok_and(|queued| queued > 0)
+    }
+}
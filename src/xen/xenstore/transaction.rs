@@ -0,0 +1,96 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenStore transactions (XS_TRANSACTION_START, XS_TRANSACTION_END)
+//!
+//! Operations done within a transaction are applied atomically when it ends.
+//! xenstored fails the commit with EAGAIN if a node used by the transaction
+//! was changed meanwhile, in which case the whole transaction is redone.
+
+use core::str;
+
+use crate::xen::XenError;
+
+use super::{XenStore, XenStoreAccess, XenStoreTransport, XsdSockmsgType};
+
+/// Number of attempts of [`XenStore::transaction`] before giving up with
+/// EAGAIN.
+pub const XS_TRANSACTION_MAX_ATTEMPTS: usize = 8;
+
+/// Transaction in progress, node operations go through [`XenStoreAccess`].
+pub struct XsTransaction<'a, T: XenStoreTransport> {
+    xs: &'a mut XenStore<T>,
+    tx_id: u32,
+}
+
+impl<T: XenStoreTransport> XsTransaction<'_, T> {
+    pub fn id(&self) -> u32 {
+        self.tx_id
+    }
+}
+
+impl<T: XenStoreTransport> XenStoreAccess for XsTransaction<'_, T> {
+    type Transport = T;
+
+    fn context(&mut self) -> (&mut XenStore<T>, u32) {
+        (self.xs, self.tx_id)
+    }
+}
+
+impl<T: XenStoreTransport> XenStore<T> {
+    /// Start a transaction, returns its id.
+    pub fn transaction_start(&mut self) -> Result<u32, XenError> {
+        let mut reply = [0; 16];
+        let reply = self.request(0, XsdSockmsgType::TransactionStart, &[b"\0"], &mut reply)?;
+        let tx_id = reply.split(|&c| c == 0).next().unwrap_or_default();
+
+        str::from_utf8(tx_id)
+            .ok()
+            .and_then(|tx_id| tx_id.parse().ok())
+            .ok_or(XenError::BadMsg)
+    }
+
+    /// End transaction `tx_id`, applying its changes if `commit`.
+    ///
+    /// Fails with EAGAIN if the transaction conflicted with another change.
+    pub fn transaction_end(&mut self, tx_id: u32, commit: bool) -> Result<(), XenError> {
+        let payload: &[u8] = if commit { b"T\0" } else { b"F\0" };
+
+        self.request_ok(tx_id, XsdSockmsgType::TransactionEnd, &[payload])
+    }
+
+    /// Run `f` within a transaction and commit it.
+    ///
+    /// The transaction is aborted if `f` fails, and redone (for up to
+    /// [`XS_TRANSACTION_MAX_ATTEMPTS`] attempts) when xenstored returns EAGAIN,
+    /// so `f` may run several times.
+    pub fn transaction<R>(
+        &mut self,
+        mut f: impl FnMut(&mut XsTransaction<'_, T>) -> Result<R, XenError>,
+    ) -> Result<R, XenError> {
+        for _ in 0..XS_TRANSACTION_MAX_ATTEMPTS {
+            let tx_id = self.transaction_start()?;
+            let mut tx = XsTransaction { xs: self, tx_id };
+
+            let value = match f(&mut tx) {
+                Ok(value) => value,
+                Err(e) => {
+                    self.transaction_end(tx_id, false).ok();
+
+                    match e {
+                        XenError::Again => continue,
+                        e => return Err(e),
+                    }
+                }
+            };
+
+            match self.transaction_end(tx_id, true) {
+                Ok(()) => return Ok(value),
+                Err(XenError::Again) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Err(XenError::Again)
+    }
+}
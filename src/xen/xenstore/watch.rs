@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: Apache-2.0
+// Copyright (C) 2025 Vates SAS - Teddy Astie
+
+//! XenStore watches (XS_WATCH, XS_UNWATCH)
+//!
+//! xenstored sends a WATCH_EVENT (path and token) whenever a watched node or
+//! one of its children changes, and once right after the watch is set.
+//! Events can arrive between a request and its reply, they are queued and
+//! dispatched to the callback registered for their token by
+//! [`XenStore::dispatch_watches`].
+
+use core::str;
+
+use crate::xen::XenError;
+
+use super::{XenStore, XenStoreTransport, XsdSockmsgType};
+
+/// Maximum number of watches.
+pub const XS_MAX_WATCHES: usize = 8;
+
+/// Maximum number of events queued until dispatched.
+pub const XS_MAX_QUEUED_EVENTS: usize = 8;
+
+/// Maximum size of a queued event (path and token), longer ones are dropped.
+pub const XS_WATCH_EVENT_MAX: usize = 512;
+
+/// Maximum length of a watch token.
+const XS_WATCH_TOKEN_MAX: usize = 32;
+
+/// Called with the changed path and the watch token.
+pub type WatchCallback<T> = fn(&mut XenStore<T>, &str, &str);
+
+struct Watch<T: XenStoreTransport> {
+    token: [u8; XS_WATCH_TOKEN_MAX],
+    token_len: usize,
+    callback: WatchCallback<T>,
+}
+
+impl<T: XenStoreTransport> Watch<T> {
+    fn token(&self) -> &[u8] {
+        &self.token[..self.token_len]
+    }
+}
+
+#[derive(Clone, Copy)]
+struct QueuedEvent {
+    payload: [u8; XS_WATCH_EVENT_MAX],
+    len: usize,
+}
+
+impl QueuedEvent {
+    /// Split the payload in (path, token).
+    fn parse(&self) -> Option<(&str, &str)> {
+        let mut fields = self.payload[..self.len].split(|&c| c == 0);
+        let path = str::from_utf8(fields.next()?).ok()?;
+        let token = str::from_utf8(fields.next()?).ok()?;
+
+        Some((path, token))
+    }
+}
+
+/// Registered watches and queued events
+pub(super) struct Watches<T: XenStoreTransport> {
+    watches: [Option<Watch<T>>; XS_MAX_WATCHES],
+    events: [QueuedEvent; XS_MAX_QUEUED_EVENTS],
+    head: usize,
+    len: usize,
+}
+
+impl<T: XenStoreTransport> Watches<T> {
+    pub(super) fn new() -> Self {
+        Self {
+            watches: [const { None }; _],
+            events: [QueuedEvent {
+                payload: [0; _],
+                len: 0,
+            }; _],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    fn find(&self, token: &[u8]) -> Option<usize> {
+        self.watches
+            .iter()
+            .position(|watch| watch.as_ref().is_some_and(|watch| watch.token() == token))
+    }
+
+    fn push(&mut self) -> Option<&mut QueuedEvent> {
+        if self.len == XS_MAX_QUEUED_EVENTS {
+            return None;
+        }
+
+        let index = (self.head + self.len) % XS_MAX_QUEUED_EVENTS;
+        self.len += 1;
+
+        Some(&mut self.events[index])
+    }
+
+    fn pop(&mut self) -> Option<QueuedEvent> {
+        if self.len == 0 {
+            return None;
+        }
+
+        let event = self.events[self.head];
+        self.head = (self.head + 1) % XS_MAX_QUEUED_EVENTS;
+        self.len -= 1;
+
+        Some(event)
+    }
+}
+
+impl<T: XenStoreTransport> XenStore<T> {
+    /// Receive the `len` bytes of payload of a watch event and queue it.
+    pub(super) fn queue_watch_event(&mut self, len: usize) -> Result<(), XenError> {
+        let event = match len {
+            ..=XS_WATCH_EVENT_MAX => self.watches.push(),
+            _ => None,
+        };
+
+        let Some(event) = event else {
+            log::warn!("XenStore: dropping watch event");
+            return self.discard(len);
+        };
+
+        event.len = len;
+        self.transport.recv(&mut event.payload[..len])
+    }
+
+    /// Watch `path` (and its children), calling `callback` with `token` on
+    /// changes.
+    ///
+    /// The callback fires once after the watch is set, on the next
+    /// [`Self::dispatch_watches`].
+    pub fn watch(
+        &mut self,
+        path: &str,
+        token: &str,
+        callback: WatchCallback<T>,
+    ) -> Result<(), XenError> {
+        if token.len() > XS_WATCH_TOKEN_MAX {
+            return Err(XenError::NameTooLong);
+        }
+
+        if self.watches.find(token.as_bytes()).is_some() {
+            return Err(XenError::Exist);
+        }
+
+        let index = self
+            .watches
+            .watches
+            .iter()
+            .position(Option::is_none)
+            .ok_or(XenError::NoSpc)?;
+
+        let mut watch = Watch {
+            token: [0; _],
+            token_len: token.len(),
+            callback,
+        };
+        watch.token[..token.len()].copy_from_slice(token.as_bytes());
+
+        // Register first, the initial event may come before the reply.
+        self.watches.watches[index] = Some(watch);
+
+        let parts = [path.as_bytes(), b"\0", token.as_bytes(), b"\0"];
+        let result = self.request_ok(0, XsdSockmsgType::Watch, &parts);
+
+        if result.is_err() {
+            self.watches.watches[index] = None;
+        }
+
+        result
+    }
+
+    /// Remove the watch on `path` with `token`.
+    ///
+    /// Its events that are still queued are dropped.
+    pub fn unwatch(&mut self, path: &str, token: &str) -> Result<(), XenError> {
+        let index = self.watches.find(token.as_bytes()).ok_or(XenError::NoEnt)?;
+
+        let parts = [path.as_bytes(), b"\0", token.as_bytes(), b"\0"];
+        self.request_ok(0, XsdSockmsgType::Unwatch, &parts)?;
+
+        self.watches.watches[index] = None;
+
+        Ok(())
+    }
+
+    /// Receive the pending messages without blocking, then run the callbacks
+    /// of the queued watch events.
+    ///
+    /// Returns the number of callbacks run.
+    pub fn dispatch_watches(&mut self) -> Result<usize, XenError> {
+        while self.transport.has_data() {
+            if let Some(header) = self.recv_header()? {
+                log::debug!("XenStore: dropping unexpected {:?} message", header.ty);
+                self.discard(header.len as usize)?;
+            }
+        }
+
+        let mut count = 0;
+
+        while let Some(event) = self.watches.pop() {
+            let Some((path, token)) = event.parse() else {
+                continue;
+            };
+
+            // Unknown tokens belong to removed watches.
+            let Some(callback) = self
+                .watches
+                .find(token.as_bytes())
+                .and_then(|index| self.watches.watches[index].as_ref())
+                .map(|watch| watch.callback)
+            else {
+                continue;
+            };
+
+            callback(self, path, token);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
+    /// Block until a watch event comes, then dispatch the queued ones.
+    pub fn wait_watches(&mut self) -> Result<usize, XenError> {
+        while self.watches.len == 0 {
+            if let Some(header) = self.recv_header()? {
+                log::debug!("XenStore: dropping unexpected {:?} message", header.ty);
+                self.discard(header.len as usize)?;
+            }
+        }
+
+        self.dispatch_watches()
+    }
+}